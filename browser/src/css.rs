@@ -1,4 +1,5 @@
 #![allow(unused)]
+use crate::dom;
 
 pub struct StyleSheet {
     pub rules: Vec<Rule>,
@@ -23,6 +24,13 @@ impl Selector {
         let c = simple.tag_name.iter().count();
         (a, b, c)
     }
+
+    /// Whether the selector selects `elem`.
+    pub fn matches(&self, elem: &dom::ElementData) -> bool {
+        match self {
+            Selector::Simple(simple_selector) => simple_selector.matches(elem),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -32,6 +40,32 @@ pub struct SimpleSelector {
     pub class: Vec<String>
 }
 
+impl SimpleSelector {
+    pub fn matches(&self, elem: &dom::ElementData) -> bool {
+        // if selector.tag_name not equal to elem.tag_name; it means dismatching,
+        // though html names match in any case
+        let same_name = |name: &String| {
+            elem.tag_name == *name || elem.is_html_element(name)
+        };
+        if self.tag_name.iter().any(|name| !same_name(name)) {
+            return false;
+        }
+
+        // if selector.id not equal to elem's id attr; it means dismatching
+        if self.id.iter().any(|id| elem.id() != Some(id)) {
+            return false;
+        }
+
+        // if any class in selector isn't contained by elem's classes; it means dismatching
+        let elem_classes = elem.classes();
+        if self.class.iter().any(|_class| !elem_classes.contains(&**_class)) {
+            return false;
+        }
+
+        true
+    }
+}
+
 pub struct Declaration {
     pub name: String,
     pub  value: Value
@@ -69,15 +103,15 @@ impl Value {
                     _ => {}
                 }
             }).count();
-        return res;
+        res
     }
     pub fn parse_as_color(s: &str) -> Option<Color> {
-        if s.starts_with("#") {
-            let color_hex = Value::parse_hex(&s[1..]);
+        if let Some(hex) = s.strip_prefix('#') {
+            let color_hex = Value::parse_hex(hex);
             Some(Color {
                 r: (color_hex >> 16 & 0xFF) as u8,
                 g: (color_hex >>  8 & 0xFF) as u8,
-                b: (color_hex >>  0 & 0xFF) as u8,
+                b: (color_hex & 0xFF) as u8,
                 a: 0,
             })
         } else if s.starts_with("rgba(") {
@@ -107,6 +141,20 @@ impl Value {
     }
 }
 
+/// Parse a whole stylesheet, like
+/// `h1 { color: red } p.note { padding: 10px }`
+pub fn parse<S: ToString>(source: S) -> StyleSheet {
+    let mut parser = Parser::new(source);
+    StyleSheet { rules: parser.parse_rules() }
+}
+
+/// Parse a comma-separated list of selectors on its own, like
+/// `h1, p.note, #main`. None if it isn't a valid selector list.
+pub fn parse_selectors<S: ToString>(source: S) -> Option<Vec<Selector>> {
+    let mut parser = Parser::new(source);
+    parser.parse_selector_list()
+}
+
 struct Parser {
     pos: usize,
     input: String,
@@ -115,7 +163,7 @@ struct Parser {
 impl Parser {
     pub fn new<S: ToString>(s: S) -> Parser {
         Parser {
-            pos: 0 as usize,
+            pos: 0,
             input: s.to_string()
         }
     }
@@ -140,29 +188,33 @@ impl Parser {
             res.push(self.consume_char());
         }
 
-        return res;
+        res
     }
     fn consume_whitespace(&mut self) {
         self.consume_while(|c| c.is_whitespace());
     }
     
     fn valid_identifier_char(c: char) -> bool {
-        match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
-            _ => false
-        }
+        matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
     }
     fn valid_declaration_name_char(c: char) -> bool {
-        match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => true,
-            _ => false
-        }
+        matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-')
     }
     fn valid_declaration_value_char(c: char) -> bool {
-        match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '#' | '\'' | '"' | '(' | ')' | ' ' | ',' => true,
-            _ => false
+        matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '#' | '\'' | '"' | '(' | ')' | ' ' | ',')
+    }
+
+    // parse rules one by one until the end of input
+    fn parse_rules(&mut self) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                break;
+            }
+            rules.push(self.parse_rule());
         }
+        rules
     }
 
     // parse a css rule, like
@@ -192,9 +244,29 @@ impl Parser {
                 c => panic!("Unexpected character {} in selector list!", c)
             }
         }
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
 
-        return selectors;
+        selectors
+    }
+    // like parse_selectors, but up to the end of input instead of a `{`
+    fn parse_selector_list(&mut self) -> Option<Vec<Selector>> {
+        let mut selectors = Vec::new();
+        loop {
+            self.consume_whitespace();
+            let start = self.pos;
+            let selector = self.parse_simple_selector();
+            if self.pos == start || selector.id.as_deref() == Some("") || selector.class.iter().any(String::is_empty) {
+                return None;
+            }
+            selectors.push(Selector::Simple(selector));
+            self.consume_whitespace();
+            if self.eof() {
+                return Some(selectors);
+            }
+            if self.consume_char() != ',' {
+                return None;
+            }
+        }
     }
     fn parse_simple_selector(&mut self) -> SimpleSelector {
         let mut selector = SimpleSelector {tag_name: None, id: None, class: Vec::new()};
@@ -220,7 +292,7 @@ impl Parser {
         selector
     }
     fn parse_identifier(&mut self) -> String {
-        self.consume_while(|c| Parser::valid_identifier_char(c))
+        self.consume_while(Parser::valid_identifier_char)
     }
 
     fn parse_declarations(&mut self) -> Vec<Declaration> {
@@ -236,19 +308,19 @@ impl Parser {
             }
         }
         
-        return declarations;
+        declarations
     }
     // parse one declaration like
     // color: red; padding: 10px; display: none
     fn parse_one_declaration(&mut self) -> Declaration {
         // parse name of one
         self.consume_whitespace();
-        let name = self.consume_while(|c| Parser::valid_declaration_name_char(c));
+        let name = self.consume_while(Parser::valid_declaration_name_char);
 
         self.consume_whitespace();
         assert!(self.consume_char() == ':');
         self.consume_whitespace();
-        let value = self.consume_while(|c| Parser::valid_declaration_value_char(c));
+        let value = self.consume_while(Parser::valid_declaration_value_char);
         let value = Parser::parse_one_declaration_value(value);
 
         Declaration {name, value}
@@ -257,7 +329,7 @@ impl Parser {
         if value_string.is_empty() { return Value::Keyword("".to_string()); }
 
         let mut value_str = &value_string[0..];
-        if value_str.starts_with(|c| c == '\'' || c == '"') {
+        if value_str.starts_with(['\'', '"']) {
             value_str = &value_str[1..value_str.len()-1];
         }
 
@@ -349,4 +421,43 @@ mod tests {
         assert_eq!(declaration3.value, Value::Keyword("none".to_owned()));
     }
 
+    #[test]
+    fn test_parse_stylesheet() {
+        let sheet = parse("\n  h1 { color: red }\n\n  p.note, #main {padding: 10px; display: block}  \n");
+        assert_eq!(sheet.rules.len(), 2);
+
+        let rule1 = &sheet.rules[0];
+        assert_eq!(rule1.selectors.len(), 1);
+        assert_eq!(rule1.declarations.len(), 1);
+        assert_eq!(rule1.declarations[0].name, "color");
+
+        let rule2 = &sheet.rules[1];
+        assert_eq!(rule2.selectors.len(), 2);
+        assert_eq!(rule2.declarations.len(), 2);
+        assert_eq!(rule2.declarations[0].value, Value::Length(10.0, Unit::Px));
+        assert_eq!(rule2.declarations[1].value, Value::Keyword("block".to_owned()));
+
+        // after sort: "#main" has the highest specificity
+        let Selector::Simple(selector) = &rule2.selectors[0];
+        assert_eq!(selector.id, Some("main".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_selectors() {
+        let selectors = parse_selectors(" h1.a.b ,#main,* ").unwrap();
+        assert_eq!(selectors.len(), 3);
+        let Selector::Simple(selector) = &selectors[0];
+        assert_eq!((selector.tag_name.as_deref(), selector.class.len()), (Some("h1"), 2));
+
+        for invalid in &["", "p,", ", p", "p {", "#", "p.", "a > b"] {
+            assert!(parse_selectors(invalid).is_none(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_empty_stylesheet() {
+        assert_eq!(parse("").rules.len(), 0);
+        assert_eq!(parse(" \n\t ").rules.len(), 0);
+    }
+
 }
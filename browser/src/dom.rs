@@ -2,14 +2,81 @@
 use std::fmt::Display;
 use std::fmt::Formatter;
 
-#[derive(Debug)]
+use crate::html::Serializer;
+
+mod content;
+mod diff;
+mod document;
+mod event;
+mod metadata;
+mod mutation;
+mod query;
+mod traversal;
+
+pub use diff::{Diff, Edit, NodePath};
+pub use document::{Children, Document, DomError, DomResult, NodeId};
+pub use event::{Event, EventDetail, EventPhase, ListenerId};
+pub use metadata::Resource;
+pub use mutation::{MutationKind, MutationObserverInit, MutationRecord, ObserverId};
+pub use traversal::{
+    Ancestors, BreadthFirst, FilterResult, NodeIterator, Postorder, Preorder, TreeWalker, SHOW_ALL,
+    SHOW_CDATA_SECTION, SHOW_COMMENT, SHOW_DOCUMENT, SHOW_DOCUMENT_TYPE, SHOW_ELEMENT,
+    SHOW_PROCESSING_INSTRUCTION, SHOW_TEXT,
+};
+
+#[derive(Debug, Clone)]
 pub struct Node {
     pub children: Vec<Node>,
 
-    pub node_type: NodeType
+    pub node_type: NodeType,
+
+    // where the node was found in the parsed input, None for nodes that
+    // weren't written there, like those built by hand or an implied <tbody>
+    pub source: Option<SourceRange>
+}
+
+/// A place in the parsed input. `line` and `column` are 1-based and
+/// `column` counts chars, while `offset` counts bytes from the start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The span of the input a node or an attribute was parsed from, `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceRange {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
 }
 
 impl Node {
+    /// The quirks mode of a document node, None for any other node.
+    pub fn quirks_mode(&self) -> Option<QuirksMode> {
+        match &self.node_type {
+            NodeType::Document(_doc) => Some(_doc.quirks_mode),
+            _ => None
+        }
+    }
+
+    /// The root element of a document node, like <html>.
+    pub fn document_element(&self) -> Option<&Node> {
+        match &self.node_type {
+            NodeType::Document(_) => self.children.iter()
+                .find(|child| matches!(child.node_type, NodeType::Element(_))),
+            _ => None
+        }
+    }
+
+    /// Where the attribute `name` of an element was found in the parsed input.
+    pub fn attribute_source(&self, name: &str) -> Option<SourceRange> {
+        match &self.node_type {
+            NodeType::Element(_elem) => _elem.attribute_sources.get(name).copied(),
+            _ => None
+        }
+    }
+
     pub fn append_child(&mut self, child: Node) {
         self.children.push(child);
     }
@@ -24,87 +91,180 @@ impl Node {
     pub fn get_attribute<K: std::string::ToString>(&self, name: K) -> Option<String> {
         match &self.node_type {
             NodeType::Element(_elem) => {
-                if let Some(_res)=_elem.attributes.get(&name.to_string()) {
-                    Some(_res.clone())
-                } else {
-                    None
-                }
+                _elem.attributes.get(&name.to_string()).cloned()
             },
             _ => None
         }
     }
 }
 
+// the markup of the node, pretty-printed with `{:#}`
 impl Display for Node {
     fn fmt(&self,  f: &mut Formatter<'_>) -> std::fmt::Result {
-        match &self.node_type {
-            NodeType::Text(_text) => writeln!(f, "{}", _text),
-            NodeType::Element(_elem) => {
-                let mut attrs_string = String::new();
-                for (name, value) in _elem.attributes.iter() {
-                    attrs_string += " ";
-                    attrs_string = format!("{}{}=\"{}\"", attrs_string,  name, value);
-                }
-
-                writeln!(f, "<{}{}>", _elem.tag_name, attrs_string)?;
-                
-                for child in &self.children {
-                    write!(f, "{}", child)?;
-                }
-                
-                writeln!(f, "</{}>", _elem.tag_name)
-            },
-            NodeType::Comment(_comment) => writeln!(f, "<!--{}-->", _comment)
-        }
+        let serializer = if f.alternate() { Serializer::new().pretty(2) } else { Serializer::new() };
+        serializer.write(self, f)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NodeType {
     Text(String),
     Element(ElementData),
-    Comment(String)
+    Comment(String),
+    Doctype(DoctypeData),
+    // target and data, like <?xml-stylesheet href="a.css"?>
+    ProcessingInstruction(String, String),
+    CData(String),
+    // the root of a whole parsed document, above the doctype and the <html> element
+    Document(DocumentData),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoctypeData {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+/// How closely a document asks to follow the standards, decided by its doctype.
+/// https://quirks.spec.whatwg.org/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+#[derive(Debug, Clone)]
+pub struct DocumentData {
+    pub quirks_mode: QuirksMode,
 }
 
-#[derive(Debug)]
+/// The namespace of an element or an attribute.
+/// https://infra.spec.whatwg.org/#namespaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+impl Namespace {
+    pub fn url(self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::Xmlns => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ElementData {
     pub tag_name: String,
-    pub attributes: AttrMap
+    pub namespace: Namespace,
+    pub attributes: AttrMap,
+    // namespaces of the attributes in one, like `xlink:href` on svg elements
+    pub attribute_namespaces: std::collections::HashMap<String, Namespace>,
+    pub attribute_sources: std::collections::HashMap<String, SourceRange>
 }
 
 impl ElementData {
+    /// Whether this is an svg or mathml element rather than an html one.
+    pub fn is_foreign(&self) -> bool {
+        self.namespace != Namespace::Html
+    }
+
+    /// Whether this is the html element `name`, whose name matches in any case.
+    pub fn is_html_element(&self, name: &str) -> bool {
+        !self.is_foreign() && self.tag_name.eq_ignore_ascii_case(name)
+    }
+
+    /// The namespace of the attribute `name`, None when it has none.
+    pub fn attribute_namespace(&self, name: &str) -> Option<Namespace> {
+        self.attribute_namespaces.get(name).copied()
+    }
+
     pub fn id(&self) -> Option<&String> {
         self.attributes.get("id")
     }
 
     pub fn classes(&self) -> std::collections::HashSet<&str>{
         match self.attributes.get("class") {
-            Some(class_list) => {class_list.split(' ').collect()},
+            Some(class_list) => {class_list.split_ascii_whitespace().collect()},
             None => std::collections::HashSet::new()
         }
     }
 }
 
-pub type AttrMap = std::collections::HashMap<String, String>;
+// attributes in the order they were set
+pub type AttrMap = indexmap::IndexMap<String, String>;
 
 pub fn text(data: String) -> Node {
-    Node { children: Vec::new(), node_type: NodeType::Text(data) }
+    Node { children: Vec::new(), node_type: NodeType::Text(data), source: None }
 }
 
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+    elem_ns(Namespace::Html, name, attrs, children)
+}
+
+pub fn elem_ns(namespace: Namespace, name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
     Node {
-        children: children,
+        children,
         node_type: NodeType::Element(ElementData {
             tag_name: name,
-            attributes: attrs
-        })
+            namespace,
+            attributes: attrs,
+            attribute_namespaces: std::collections::HashMap::new(),
+            attribute_sources: std::collections::HashMap::new()
+        }),
+        source: None
     }
 }
 
 pub fn comment(content: String) -> Node {
     Node {
         children: Vec::new(),
-        node_type: NodeType::Comment(content)
+        node_type: NodeType::Comment(content),
+        source: None
+    }
+}
+
+pub fn doctype(name: String, public_id: Option<String>, system_id: Option<String>) -> Node {
+    Node {
+        children: Vec::new(),
+        node_type: NodeType::Doctype(DoctypeData { name, public_id, system_id }),
+        source: None
+    }
+}
+
+pub fn processing_instruction(target: String, data: String) -> Node {
+    Node {
+        children: Vec::new(),
+        node_type: NodeType::ProcessingInstruction(target, data),
+        source: None
+    }
+}
+
+pub fn cdata(data: String) -> Node {
+    Node {
+        children: Vec::new(),
+        node_type: NodeType::CData(data),
+        source: None
+    }
+}
+
+pub fn document(quirks_mode: QuirksMode, children: Vec<Node>) -> Node {
+    Node {
+        children,
+        node_type: NodeType::Document(DocumentData { quirks_mode }),
+        source: None
     }
 }
 
@@ -112,6 +272,11 @@ pub fn comment(content: String) -> Node {
 mod tests {
     use super::*;
 
+    // a document whose root is the element parsed from `source`
+    pub(super) fn parse(source: &str) -> Document {
+        Document::from_node(crate::html::Parser::new(source).parse().unwrap())
+    }
+
     #[test]
     fn test_dom() {
         //<html><body>hello<!-- comment --></body></html>
@@ -124,4 +289,24 @@ mod tests {
         assert_eq!(html.children.len(), 1);
         assert_eq!(html.children[0].children.len(), 2);
     }
+
+    #[test]
+    fn test_document() {
+        let html = elem("html".to_owned(), AttrMap::new(), Vec::new());
+        let doc = document(QuirksMode::NoQuirks, vec![
+            doctype("html".to_owned(), None, None),
+            comment(" top ".to_owned()),
+            html,
+        ]);
+
+        assert_eq!(doc.quirks_mode(), Some(QuirksMode::NoQuirks));
+        assert_eq!(doc.children[0].quirks_mode(), None);
+        assert!(matches!(&doc.document_element().unwrap().node_type, NodeType::Element(e) if e.tag_name == "html"));
+        assert_eq!(doc.to_string(), "<!DOCTYPE html><!-- top --><html></html>");
+
+        let legacy = doctype("html".to_owned(), Some("-//W3C//DTD HTML 4.01//EN".to_owned()), None);
+        assert_eq!(legacy.to_string(), "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\">");
+        assert_eq!(cdata("x<y".to_owned()).to_string(), "<![CDATA[x<y]]>");
+        assert_eq!(processing_instruction("xml".to_owned(), "version=\"1.0\"".to_owned()).to_string(), "<?xml version=\"1.0\"?>");
+    }
 }
@@ -0,0 +1,138 @@
+// The text and the markup of nodes, like textContent and innerHTML
+
+use super::{Document, Node, NodeId, NodeType};
+use crate::{css, html, style};
+
+fn push_text(node_type: &NodeType, result: &mut String) {
+    if let NodeType::Text(text) | NodeType::CData(text) = node_type {
+        result.push_str(text);
+    }
+}
+
+impl Node {
+    /// The text of every text node under this one in document order, or
+    /// the data of a text, comment, cdata or processing instruction node.
+    pub fn text_content(&self) -> String {
+        match &self.node_type {
+            NodeType::Comment(data) | NodeType::ProcessingInstruction(_, data) => data.clone(),
+            _ => {
+                let mut result = String::new();
+                for node in self.preorder() {
+                    push_text(&node.node_type, &mut result);
+                }
+                result
+            }
+        }
+    }
+
+    /// The text of the node the way browsers show it with no stylesheet of
+    /// the page, see `style::StyledNode::inner_text` to use one.
+    pub fn inner_text(&self) -> String {
+        let style_sheet = css::parse("");
+        style::style_tree(self, &style_sheet).inner_text()
+    }
+
+    /// The markup of the children of the node.
+    pub fn inner_html(&self) -> String {
+        let serializer = html::Serializer::new();
+        self.children.iter().map(|child| serializer.serialize(child)).collect()
+    }
+
+    /// The markup of the node and its children.
+    pub fn outer_html(&self) -> String {
+        html::Serializer::new().serialize(self)
+    }
+
+    /// Replace the children of an element with `markup`, parsed the way its
+    /// content is, so `<li>` items for a `<ul>` and text for a `<textarea>`.
+    /// Nodes other than elements are left as they are.
+    pub fn set_inner_html(&mut self, markup: &str) {
+        if let NodeType::Element(elem) = &self.node_type {
+            self.children = html::parse_fragment(elem, markup);
+        }
+    }
+}
+
+impl Document {
+    /// Like `Node::text_content`, for the node `id`.
+    pub fn text_content(&self, id: NodeId) -> String {
+        match self.node_type(id) {
+            NodeType::Comment(data) | NodeType::ProcessingInstruction(_, data) => data.clone(),
+            _ => {
+                let mut result = String::new();
+                for node in self.preorder(id) {
+                    push_text(self.node_type(node), &mut result);
+                }
+                result
+            }
+        }
+    }
+
+    pub fn inner_html(&self, id: NodeId) -> String {
+        self.children(id).map(|child| self.outer_html(child)).collect()
+    }
+
+    pub fn outer_html(&self, id: NodeId) -> String {
+        self.to_node(id).outer_html()
+    }
+
+    /// Like `Node::set_inner_html`, for the node `id`.
+    pub fn set_inner_html(&mut self, id: NodeId, markup: &str) {
+        let nodes = match self.element(id) {
+            Some(elem) => html::parse_fragment(elem, markup),
+            None => return,
+        };
+        let children = nodes.into_iter().map(|node| self.create_tree(node)).collect();
+        self.replace_all(id, children);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    #[test]
+    fn test_text_content() {
+        let div = Parser::new("<div>a<p>b<!--c--><b>d</b></p>\n<script>f</script></div>").parse().unwrap();
+        assert_eq!(div.text_content(), "abd\nf");
+        assert_eq!(div.children[1].children[1].text_content(), "c");
+
+        let document = Document::from_node(div);
+        let p = document.children(document.root()).nth(1).unwrap();
+        assert_eq!(document.text_content(document.root()), "abd\nf");
+        assert_eq!(document.text_content(p), "bd");
+    }
+
+    #[test]
+    fn test_inner_text() {
+        let html = Parser::new("<body><h1>Title</h1><div>one <b>two</b>\n   three<br>four</div><script>x</script><ul><li>a</li><li>b</li></ul></body>").parse().unwrap();
+        assert_eq!(html.inner_text(), "Title\none two three\nfour\na\nb");
+        // an element that isn't shown gives its text as it is
+        assert_eq!(html.children[2].inner_text(), "x");
+    }
+
+    #[test]
+    fn test_inner_html() {
+        let mut ul = Parser::new("<ul class=a><li>1 &amp; 2</li></ul>").parse().unwrap();
+        assert_eq!(ul.inner_html(), "<li>1 &amp; 2</li>");
+        assert_eq!(ul.outer_html(), "<ul class=\"a\"><li>1 &amp; 2</li></ul>");
+
+        ul.set_inner_html("<li>x<li>y");
+        assert_eq!(ul.outer_html(), "<ul class=\"a\"><li>x</li><li>y</li></ul>");
+        let mut text = ul.children[0].children[0].clone();
+        text.set_inner_html("<b>ignored</b>");
+        assert_eq!(text.outer_html(), "x");
+
+        let mut document = Parser::new("<div id=d><textarea></textarea><p id=old>p</p></div>").tolerant().parse_tree().unwrap();
+        let div = document.get_element_by_id("d").unwrap();
+        let textarea = document.first_child(div).unwrap();
+        document.set_inner_html(textarea, "<b>not bold</b>");
+        assert_eq!(document.inner_html(textarea), "&lt;b&gt;not bold&lt;/b&gt;");
+        document.set_inner_html(div, "<table><tr><td id=new>1</table>");
+        assert_eq!(document.inner_html(div), "<table><tbody><tr><td id=\"new\">1</td></tr></tbody></table>");
+        // the old children are gone from the id index and the new ones are in it
+        assert_eq!(document.get_element_by_id("old"), None);
+        assert_eq!(document.text_content(document.get_element_by_id("new").unwrap()), "1");
+    }
+}
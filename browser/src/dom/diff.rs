@@ -0,0 +1,512 @@
+// The differences between two trees as the edits that turn one into the
+// other, and a report of them by where they are in the tree
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::{DomError, DomResult, Namespace, Node, NodeType};
+
+/// The indexes of the children from the root down to a node, empty for the
+/// root itself.
+pub type NodePath = Vec<usize>;
+
+/// One change to a tree. Each path is taken in the tree as the edits before
+/// it left it.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    // `node` put in the tree so that it is at `path`
+    Insert { path: NodePath, node: Node },
+    // the node at `path` taken out, which was `node`
+    Remove { path: NodePath, node: Node },
+    // the node at `from` taken out, then put back so that it is at `to`
+    Move { from: NodePath, to: NodePath },
+    // an attribute of the element at `path` set, in `namespace` if it has
+    // one, or removed when `new` is None
+    SetAttribute { path: NodePath, name: String, namespace: Option<Namespace>, old: Option<String>, new: Option<String> },
+    // the data of the text, comment or cdata at `path` changed
+    SetText { path: NodePath, old: String, new: String },
+    // the node at `path` swapped for one of another kind, only done to the root
+    Replace { path: NodePath, node: Node },
+}
+
+/// The edits that turn one tree into another, in the order they apply.
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub edits: Vec<Edit>,
+}
+
+impl Node {
+    /// The edits that turn this tree into `new`. Children that are equal
+    /// in both are kept, the ones that changed places are moved, and the
+    /// others of the same kind are changed rather than replaced. Sources
+    /// aren't compared.
+    pub fn diff(&self, new: &Node) -> Diff {
+        let mut edits = Vec::new();
+        diff_node(self, new, &mut Vec::new(), &mut edits);
+        Diff { edits }
+    }
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Make the edits to `node`, which should be the tree the diff was made
+    /// from. Gives `DomError::NotFound` for a path that isn't in the tree,
+    /// and `DomError::HierarchyRequest` for a node put where it can't go,
+    /// leaving the edits before it made.
+    pub fn apply(&self, node: &mut Node) -> DomResult<()> {
+        self.edits.iter().try_for_each(|edit| apply_edit(node, edit))
+    }
+
+    /// The edits one per line, each with the path of the node it changes in
+    /// the tree as it is then, like `/html/body/p[2]` for the second `<p>`
+    /// of the body. `old` is the tree the diff was made from, and the errors
+    /// are those of `apply` when it isn't.
+    pub fn report(&self, old: &Node) -> DomResult<String> {
+        let mut tree = old.clone();
+        let mut report = String::new();
+        for edit in &self.edits {
+            // where the edit takes something from, named before it is made
+            let at = match edit {
+                Edit::Insert { .. } => String::new(),
+                Edit::Remove { path, .. } | Edit::Move { from: path, .. } | Edit::SetAttribute { path, .. }
+                    | Edit::SetText { path, .. } | Edit::Replace { path, .. } => path_name(&tree, path)?,
+            };
+            let replaced = match edit {
+                Edit::Replace { path, .. } => summary(node_ref(&tree, path)?),
+                _ => String::new(),
+            };
+            apply_edit(&mut tree, edit)?;
+            let line = match edit {
+                Edit::Insert { path, node } => format!("+ {}: {}", path_name(&tree, path)?, summary(node)),
+                Edit::Remove { node, .. } => format!("- {}: {}", at, summary(node)),
+                Edit::Move { to, .. } => format!("> {} -> {}", at, path_name(&tree, to)?),
+                Edit::SetAttribute { name, old, new, .. } => match (old, new) {
+                    (Some(old), Some(new)) => format!("~ {} @{}: {:?} -> {:?}", at, name, old, new),
+                    (None, Some(new)) => format!("+ {} @{}={:?}", at, name, new),
+                    (Some(old), None) => format!("- {} @{}={:?}", at, name, old),
+                    (None, None) => continue,
+                },
+                Edit::SetText { old, new, .. } => format!("~ {}: {:?} -> {:?}", at, old, new),
+                Edit::Replace { node, .. } => format!("~ {}: {} -> {}", at, replaced, summary(node)),
+            };
+            report.push_str(&line);
+            report.push('\n');
+        }
+        Ok(report)
+    }
+}
+
+// the node itself without its children, sources left out
+fn same_node(a: &Node, b: &Node) -> bool {
+    match (&a.node_type, &b.node_type) {
+        (NodeType::Text(a), NodeType::Text(b))
+            | (NodeType::Comment(a), NodeType::Comment(b))
+            | (NodeType::CData(a), NodeType::CData(b)) => a == b,
+        (NodeType::ProcessingInstruction(a, a_data), NodeType::ProcessingInstruction(b, b_data)) => a == b && a_data == b_data,
+        (NodeType::Doctype(a), NodeType::Doctype(b)) => a == b,
+        (NodeType::Document(a), NodeType::Document(b)) => a.quirks_mode == b.quirks_mode,
+        (NodeType::Element(a), NodeType::Element(b)) => {
+            a.tag_name == b.tag_name && a.namespace == b.namespace && a.attributes == b.attributes
+                && a.attribute_namespaces == b.attribute_namespaces
+        }
+        _ => false,
+    }
+}
+
+fn same_tree(a: &Node, b: &Node) -> bool {
+    same_node(a, b) && a.children.len() == b.children.len()
+        && a.children.iter().zip(&b.children).all(|(a, b)| same_tree(a, b))
+}
+
+// whether the edits can turn one node into the other in place: elements
+// with the same name and no different ids, and text into text
+fn same_kind(a: &Node, b: &Node) -> bool {
+    match (&a.node_type, &b.node_type) {
+        (NodeType::Text(_), NodeType::Text(_))
+            | (NodeType::Comment(_), NodeType::Comment(_))
+            | (NodeType::CData(_), NodeType::CData(_)) => true,
+        (NodeType::Element(a), NodeType::Element(b)) => {
+            a.tag_name == b.tag_name && a.namespace == b.namespace
+                && a.id().zip(b.id()).is_none_or(|(a, b)| a == b)
+        }
+        _ => same_node(a, b),
+    }
+}
+
+// a hash of the whole tree, equal for equal trees
+fn hash_tree(node: &Node) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_node(node, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_node(node: &Node, hasher: &mut DefaultHasher) {
+    match &node.node_type {
+        NodeType::Text(text) => (0, text).hash(hasher),
+        NodeType::Comment(text) => (1, text).hash(hasher),
+        NodeType::CData(text) => (2, text).hash(hasher),
+        NodeType::ProcessingInstruction(target, data) => (3, target, data).hash(hasher),
+        NodeType::Doctype(doctype) => (4, &doctype.name, &doctype.public_id, &doctype.system_id).hash(hasher),
+        NodeType::Document(_) => 5.hash(hasher),
+        NodeType::Element(elem) => {
+            (6, &elem.tag_name, elem.namespace.url()).hash(hasher);
+            // attributes are equal in any order
+            let mut attributes: Vec<(&String, &String, Option<&str>)> = elem.attributes.iter()
+                .map(|(name, value)| (name, value, elem.attribute_namespace(name).map(Namespace::url)))
+                .collect();
+            attributes.sort();
+            attributes.hash(hasher);
+        }
+    }
+    node.children.len().hash(hasher);
+    for child in &node.children {
+        hash_node(child, hasher);
+    }
+}
+
+fn child_path(path: &[usize], index: usize) -> NodePath {
+    let mut path = path.to_vec();
+    path.push(index);
+    path
+}
+
+fn diff_node(old: &Node, new: &Node, path: &mut NodePath, edits: &mut Vec<Edit>) {
+    if !same_kind(old, new) {
+        edits.push(Edit::Replace { path: path.clone(), node: new.clone() });
+        return;
+    }
+    match (&old.node_type, &new.node_type) {
+        (NodeType::Text(old_text), NodeType::Text(new_text))
+            | (NodeType::Comment(old_text), NodeType::Comment(new_text))
+            | (NodeType::CData(old_text), NodeType::CData(new_text)) if old_text != new_text => {
+            edits.push(Edit::SetText { path: path.clone(), old: old_text.clone(), new: new_text.clone() });
+        }
+        (NodeType::Element(old_elem), NodeType::Element(new_elem)) => {
+            for (name, value) in &old_elem.attributes {
+                if !new_elem.attributes.contains_key(name) {
+                    edits.push(Edit::SetAttribute {
+                        path: path.clone(), name: name.clone(), namespace: None, old: Some(value.clone()), new: None,
+                    });
+                }
+            }
+            for (name, value) in &new_elem.attributes {
+                let old_value = old_elem.attributes.get(name);
+                let namespace = new_elem.attribute_namespace(name);
+                if old_value != Some(value) || old_elem.attribute_namespace(name) != namespace {
+                    edits.push(Edit::SetAttribute {
+                        path: path.clone(), name: name.clone(), namespace, old: old_value.cloned(), new: Some(value.clone()),
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+    diff_children(&old.children, &new.children, path, edits);
+}
+
+// the pairs of equal old and new children, as many as keep their order
+fn longest_common(old: &[Node], new: &[Node], equal: &dyn Fn(usize, usize) -> bool) -> Vec<(usize, usize)> {
+    let (m, n) = (old.len(), new.len());
+    // lengths[i][j] is the longest for old[i..] and new[j..]
+    let mut lengths = vec![vec![0u32; n + 1]; m + 1];
+    for i in (0..m).rev() {
+        for j in (0..n).rev() {
+            lengths[i][j] = if equal(i, j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < m && j < n {
+        if equal(i, j) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+fn diff_children(old: &[Node], new: &[Node], path: &mut NodePath, edits: &mut Vec<Edit>) {
+    let old_hashes: Vec<u64> = old.iter().map(hash_tree).collect();
+    let new_hashes: Vec<u64> = new.iter().map(hash_tree).collect();
+    let equal = |i: usize, j: usize| old_hashes[i] == new_hashes[j] && same_tree(&old[i], &new[j]);
+    let kept = longest_common(old, new, &equal);
+
+    // the old child each new one comes from, and whether it needs changing
+    let mut sources: Vec<Option<(usize, bool)>> = vec![None; new.len()];
+    let mut used = vec![false; old.len()];
+    for &(i, j) in &kept {
+        sources[j] = Some((i, false));
+        used[i] = true;
+    }
+    // equal children that changed places
+    for (j, source) in sources.iter_mut().enumerate() {
+        if source.is_none() {
+            if let Some(i) = (0..old.len()).find(|&i| !used[i] && equal(i, j)) {
+                *source = Some((i, false));
+                used[i] = true;
+            }
+        }
+    }
+    // between the kept ones, children of the same kind are changed in place
+    let bounds = std::iter::once((0, 0))
+        .chain(kept.iter().map(|&(i, j)| (i + 1, j + 1)))
+        .zip(kept.iter().copied().chain(std::iter::once((old.len(), new.len()))));
+    for ((old_start, new_start), (old_end, new_end)) in bounds {
+        let mut next = old_start;
+        for j in new_start..new_end {
+            if sources[j].is_some() {
+                continue;
+            }
+            if let Some(i) = (next..old_end).find(|&i| !used[i] && same_kind(&old[i], &new[j])) {
+                sources[j] = Some((i, true));
+                used[i] = true;
+                next = i + 1;
+            }
+        }
+    }
+
+    // the old children that are in the tree at each index as it changes
+    let mut current: Vec<Option<usize>> = (0..old.len()).map(Some).collect();
+    for i in (0..old.len()).filter(|&i| !used[i]) {
+        let index = current.iter().position(|&child| child == Some(i)).unwrap();
+        edits.push(Edit::Remove { path: child_path(path, index), node: old[i].clone() });
+        current.remove(index);
+    }
+    for (j, source) in sources.into_iter().enumerate() {
+        match source {
+            Some((i, changed)) => {
+                let index = current.iter().position(|&child| child == Some(i)).unwrap();
+                if index != j {
+                    edits.push(Edit::Move { from: child_path(path, index), to: child_path(path, j) });
+                    current.remove(index);
+                    current.insert(j, Some(i));
+                }
+                if changed {
+                    path.push(j);
+                    diff_node(&old[i], &new[j], path, edits);
+                    path.pop();
+                }
+            }
+            None => {
+                edits.push(Edit::Insert { path: child_path(path, j), node: new[j].clone() });
+                current.insert(j, None);
+            }
+        }
+    }
+}
+
+fn node_ref<'a>(root: &'a Node, path: &[usize]) -> DomResult<&'a Node> {
+    path.iter().try_fold(root, |node, &index| node.children.get(index).ok_or(DomError::NotFound))
+}
+
+fn node_at<'a>(root: &'a mut Node, path: &[usize]) -> DomResult<&'a mut Node> {
+    path.iter().try_fold(root, |node, &index| node.children.get_mut(index).ok_or(DomError::NotFound))
+}
+
+fn take(root: &mut Node, path: &[usize]) -> DomResult<Node> {
+    let (&index, parent) = path.split_last().ok_or(DomError::HierarchyRequest)?;
+    let parent = node_at(root, parent)?;
+    if index >= parent.children.len() {
+        return Err(DomError::NotFound);
+    }
+    Ok(parent.children.remove(index))
+}
+
+fn put(root: &mut Node, path: &[usize], node: Node) -> DomResult<()> {
+    let (&index, parent) = path.split_last().ok_or(DomError::HierarchyRequest)?;
+    let parent = node_at(root, parent)?;
+    if !matches!(parent.node_type, NodeType::Element(_) | NodeType::Document(_)) {
+        return Err(DomError::HierarchyRequest);
+    }
+    if index > parent.children.len() {
+        return Err(DomError::NotFound);
+    }
+    parent.children.insert(index, node);
+    Ok(())
+}
+
+fn apply_edit(root: &mut Node, edit: &Edit) -> DomResult<()> {
+    match edit {
+        Edit::Insert { path, node } => put(root, path, node.clone()),
+        Edit::Remove { path, .. } => take(root, path).map(|_| ()),
+        Edit::Move { from, to } => {
+            let node = take(root, from)?;
+            put(root, to, node)
+        }
+        Edit::SetAttribute { path, name, namespace, new, .. } => match &mut node_at(root, path)?.node_type {
+            NodeType::Element(elem) => {
+                // the value isn't where it was parsed from any more
+                elem.attribute_sources.remove(name);
+                elem.attribute_namespaces.remove(name);
+                match new {
+                    Some(value) => {
+                        elem.attributes.insert(name.clone(), value.clone());
+                        if let Some(namespace) = namespace {
+                            elem.attribute_namespaces.insert(name.clone(), *namespace);
+                        }
+                    }
+                    None => {
+                        elem.attributes.shift_remove(name);
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(DomError::NotFound),
+        },
+        Edit::SetText { path, new, .. } => match &mut node_at(root, path)?.node_type {
+            NodeType::Text(text) | NodeType::Comment(text) | NodeType::CData(text) => {
+                *text = new.clone();
+                Ok(())
+            }
+            _ => Err(DomError::NotFound),
+        },
+        Edit::Replace { path, node } => {
+            *node_at(root, path)? = node.clone();
+            Ok(())
+        }
+    }
+}
+
+// the name of a node in a path, like the DOM's nodeName
+fn step_name(node: &Node) -> String {
+    match &node.node_type {
+        NodeType::Element(elem) => elem.tag_name.clone(),
+        NodeType::Text(_) => "#text".to_string(),
+        NodeType::Comment(_) => "#comment".to_string(),
+        NodeType::CData(_) => "#cdata-section".to_string(),
+        NodeType::Doctype(_) => "#doctype".to_string(),
+        NodeType::ProcessingInstruction(target, _) => format!("?{}", target),
+        NodeType::Document(_) => "#document".to_string(),
+    }
+}
+
+// the path by names, with the place among the siblings of the same name
+// when there are several
+fn path_name(root: &Node, path: &[usize]) -> DomResult<String> {
+    let mut name = String::new();
+    let mut node = root;
+    for &index in path {
+        let child = node.children.get(index).ok_or(DomError::NotFound)?;
+        let step = step_name(child);
+        let same_name = |sibling: &&Node| step_name(sibling) == step;
+        let position = node.children[..index].iter().filter(same_name).count() + 1;
+        if position > 1 || node.children[index + 1..].iter().any(|sibling| same_name(&sibling)) {
+            name.push_str(&format!("/{}[{}]", step, position));
+        } else {
+            name.push_str(&format!("/{}", step));
+        }
+        node = child;
+    }
+    if name.is_empty() {
+        name.push('/');
+    }
+    Ok(name)
+}
+
+// the markup of a node, shortened to fit a line
+fn summary(node: &Node) -> String {
+    const LENGTH: usize = 60;
+    let markup = node.outer_html();
+    match markup.char_indices().nth(LENGTH) {
+        Some((end, _)) => format!("{}...", &markup[..end]),
+        None => markup,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    // the diff turns the old tree into the new one
+    fn check(old: &str, new: &str) -> Diff {
+        let (mut old, new) = (Parser::new(old).parse().unwrap(), Parser::new(new).parse().unwrap());
+        let diff = old.diff(&new);
+        diff.apply(&mut old).unwrap();
+        assert!(same_tree(&old, &new), "{} isn't {}", old, new);
+        assert!(old.diff(&new).is_empty());
+        diff
+    }
+
+    #[test]
+    fn test_diff_and_apply() {
+        assert!(check("<p class=a>text</p>", "<p class=a>text</p>").is_empty());
+        assert_eq!(check("<p class=a title=x>text</p>", "<p title=y lang=en>other</p>").edits.len(), 4);
+        // elements with other ids are replaced rather than changed
+        assert!(matches!(&check("<p id=a>text</p>", "<p id=b>text</p>").edits[..], [Edit::Replace { .. }]));
+        // equal children are kept and the others changed in place
+        let diff = check("<ul><li>a</li><li>b</li><li>c</li></ul>", "<ul><li>a</li><li>x</li><li>c</li><li>d</li></ul>");
+        assert!(matches!(&diff.edits[..], [Edit::SetText { path, .. }, Edit::Insert { .. }] if path == &vec![1, 0]));
+        // a reordering is a move
+        let diff = check("<ul><li>a</li><li>b</li><li>c</li></ul>", "<ul><li>c</li><li>a</li><li>b</li></ul>");
+        assert!(matches!(&diff.edits[..], [Edit::Move { from, to }] if from == &vec![2] && to == &vec![0]));
+        // elements of another kind are removed and inserted
+        let diff = check("<div><p>a</p><!--c--></div>", "<div><span>a</span>text</div>");
+        assert_eq!(diff.edits.len(), 4);
+        check("<div id=a><p>deep <b>down</b> here</p></div>", "<div><p>deep <i>down</i></p><p>here</p></div>");
+        check("<p>a</p>", "<!--root-->");
+        check("<table><tr><td>1</td><td>2</td></tr></table>", "<table><tr><td>2</td><td>1</td><td>3</td></tr></table>");
+    }
+
+    #[test]
+    fn test_attribute_namespaces() {
+        let old = Parser::new("<svg><a xlink:href=x></a></svg>").tolerant().parse_document().unwrap();
+        // the same markup, but with an attribute in no namespace
+        let mut new = old.clone();
+        let a = node_at(&mut new, &[0, 1, 0, 0]).unwrap();
+        match &mut a.node_type {
+            NodeType::Element(elem) => assert_eq!(elem.attribute_namespaces.remove("xlink:href"), Some(Namespace::XLink)),
+            _ => panic!("not the <a>"),
+        }
+        let diff = new.diff(&old);
+        assert!(matches!(&diff.edits[..], [Edit::SetAttribute { namespace: Some(Namespace::XLink), .. }]));
+        diff.apply(&mut new).unwrap();
+        assert!(same_tree(&new, &old));
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_apply_to_another_tree() {
+        let old = Parser::new("<ul><li>a</li><li>b</li></ul>").parse().unwrap();
+        let mut other = Parser::new("<ul><li>a</li></ul>").parse().unwrap();
+        let diff = old.diff(&other);
+        assert_eq!(diff.apply(&mut other), Err(DomError::NotFound));
+        let diff = Diff { edits: vec![Edit::Insert { path: vec![0, 0], node: Parser::new("<b>b</b>").parse().unwrap() }] };
+        assert_eq!(diff.apply(&mut Parser::new("<p>text</p>").parse().unwrap()), Err(DomError::HierarchyRequest));
+    }
+
+    #[test]
+    fn test_report() {
+        let old = Parser::new("<html><body><p class=a>one</p><p>two</p><ul><li>x</li><li>y</li></ul></body></html>")
+            .tolerant().parse_document().unwrap();
+        let new = Parser::new("<html><body><p class=b>one</p><ul><li>y</li><li>x</li></ul><p>three</p></body></html>")
+            .tolerant().parse_document().unwrap();
+        let diff = old.diff(&new);
+        assert_eq!(diff.report(&old).unwrap(), "\
+- /html/body/p[2]: <p>two</p>
+~ /html/body/p @class: \"a\" -> \"b\"
+> /html/body/ul/li[2] -> /html/body/ul/li[1]
++ /html/body/p[2]: <p>three</p>
+");
+        let mut patched = old.clone();
+        diff.apply(&mut patched).unwrap();
+        assert_eq!(patched.to_string(), new.to_string());
+        // a tree the diff wasn't made from gives an error, not a wrong report
+        assert_eq!(diff.report(&new).err(), Some(DomError::NotFound));
+
+        let (p, b) = (Parser::new("<p>a</p>").parse().unwrap(), Parser::new("<b>a</b>").parse().unwrap());
+        assert_eq!(p.diff(&b).report(&p).unwrap(), "~ /: <p>a</p> -> <b>a</b>\n");
+    }
+}
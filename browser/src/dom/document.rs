@@ -0,0 +1,808 @@
+// A tree kept in one arena, where every node knows its parent and siblings
+// as well as its children
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use super::event::EventListeners;
+use super::mutation::MutationObservers;
+use super::{ElementData, Node, NodeType, SourceRange};
+
+/// Why a change to a `Document` was refused, named like the DOM exceptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomError {
+    // the node can't go there, like an element into itself or into a text node
+    HierarchyRequest,
+    // the reference node isn't a child of the given parent
+    NotFound,
+    // a selector that doesn't parse
+    Syntax,
+    // options that ask for nothing, like an observer of no kind of change
+    Type,
+}
+
+impl Display for DomError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomError::HierarchyRequest => write!(f, "the node can't be inserted there"),
+            DomError::NotFound => write!(f, "the node isn't a child of its given parent"),
+            DomError::Syntax => write!(f, "the selector is invalid"),
+            DomError::Type => write!(f, "the options are invalid"),
+        }
+    }
+}
+
+impl std::error::Error for DomError {}
+
+pub type DomResult<T> = Result<T, DomError>;
+
+/// A node of a `Document`. It stays valid as long as the document does,
+/// even once the node is taken out of the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug)]
+struct TreeNode {
+    node_type: NodeType,
+    source: Option<SourceRange>,
+    parent: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+/// A tree of nodes that can be walked in every direction, up to the parent
+/// and across to the siblings as well as down to the children.
+///
+/// Nodes live in an arena owned by the document and are referred to by
+/// `NodeId`. `Document::from_node` builds one from an owned `Node` tree and
+/// `to_node` gives the owned tree back.
+#[derive(Debug)]
+pub struct Document {
+    nodes: Vec<TreeNode>,
+    root: NodeId,
+    // the elements in the tree by their id, kept up to date by every change
+    ids: HashMap<String, Vec<NodeId>>,
+    // the encoding the document was decoded from, when it's known
+    pub(super) charset: Option<&'static str>,
+    pub(super) listeners: EventListeners,
+    pub(super) observers: MutationObservers,
+}
+
+impl Document {
+    /// A document holding just `root`.
+    pub fn new(root: NodeType) -> Document {
+        let mut document = Document { nodes: Vec::new(), root: NodeId(0), ids: HashMap::new(), charset: None,
+            listeners: EventListeners::default(), observers: MutationObservers::default() };
+        document.root = document.create_node(root);
+        document.index_ids(document.root, true);
+        document
+    }
+
+    /// Move the nodes of an owned tree into an arena, keeping their sources.
+    pub fn from_node(node: Node) -> Document {
+        let mut document = Document::new(node.node_type);
+        document.nodes[0].source = node.source;
+        let root = document.root;
+        for child in node.children {
+            let child = document.create_tree(child);
+            document.link(root, child, None);
+        }
+        document
+    }
+
+    /// Move the nodes of an owned tree into the arena, as a subtree that
+    /// isn't in the tree until it is appended somewhere.
+    pub fn create_tree(&mut self, node: Node) -> NodeId {
+        let id = self.create_node(node.node_type);
+        self.node_mut(id).source = node.source;
+        for child in node.children {
+            let child = self.create_tree(child);
+            self.link(id, child, None);
+        }
+        id
+    }
+
+    /// The node `id` and everything under it as an owned tree.
+    pub fn to_node(&self, id: NodeId) -> Node {
+        Node {
+            children: self.children(id).map(|child| self.to_node(child)).collect(),
+            node_type: self.node(id).node_type.clone(),
+            source: self.node(id).source,
+        }
+    }
+
+    fn node(&self, id: NodeId) -> &TreeNode {
+        &self.nodes[id.0]
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut TreeNode {
+        &mut self.nodes[id.0]
+    }
+
+    /// The node at the top of the tree, usually a `NodeType::Document`.
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// A node that belongs to the document but isn't in the tree until it
+    /// is appended somewhere.
+    pub fn create_node(&mut self, node_type: NodeType) -> NodeId {
+        self.nodes.push(TreeNode {
+            node_type,
+            source: None,
+            parent: None,
+            previous_sibling: None,
+            next_sibling: None,
+            first_child: None,
+            last_child: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Add `child` after the last child of `parent`, taking it out of
+    /// wherever it was first.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> DomResult<()> {
+        self.insert_before(parent, child, None)
+    }
+
+    /// Add `child` to `parent` right before `reference`, or last when
+    /// `reference` is None, taking it out of wherever it was first.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> DomResult<()> {
+        self.check_insert(parent, child, reference, None)?;
+        if reference.is_some_and(|reference| self.parent(reference) != Some(parent)) {
+            return Err(DomError::NotFound);
+        }
+        // inserting a node before itself leaves it where it is
+        let reference = if reference == Some(child) { self.next_sibling(child) } else { reference };
+        self.detach(child);
+        let previous = match reference {
+            Some(reference) => self.previous_sibling(reference),
+            None => self.last_child(parent),
+        };
+        self.link(parent, child, reference);
+        self.queue_child_list(parent, vec![child], Vec::new(), previous, reference);
+        Ok(())
+    }
+
+    /// Take `child` out of `parent`. It stays in the arena and can be put back.
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> DomResult<()> {
+        if self.parent(child) != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.detach(child);
+        Ok(())
+    }
+
+    /// Put `new_child` where `old_child` is in `parent`, taking `old_child` out.
+    pub fn replace_child(&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) -> DomResult<()> {
+        if self.parent(old_child) != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.check_insert(parent, new_child, None, Some(old_child))?;
+        if new_child == old_child {
+            return Ok(());
+        }
+        let mut reference = self.next_sibling(old_child);
+        if reference == Some(new_child) {
+            reference = self.next_sibling(new_child);
+        }
+        let mut previous = self.previous_sibling(old_child);
+        if previous == Some(new_child) {
+            previous = self.previous_sibling(new_child);
+        }
+        self.detach(new_child);
+        self.unlink(old_child);
+        self.link(parent, new_child, reference);
+        self.queue_child_list(parent, vec![new_child], vec![old_child], previous, reference);
+        Ok(())
+    }
+
+    /// Take the node out of the tree, if it has a parent.
+    pub fn detach(&mut self, id: NodeId) {
+        if let Some(parent) = self.parent(id) {
+            let (previous, next) = (self.previous_sibling(id), self.next_sibling(id));
+            self.unlink(id);
+            self.queue_child_list(parent, Vec::new(), vec![id], previous, next);
+        }
+    }
+
+    /// Put `nodes`, which aren't in the tree, in place of all the children
+    /// of `parent`, as one change.
+    pub(super) fn replace_all(&mut self, parent: NodeId, nodes: Vec<NodeId>) {
+        let removed: Vec<NodeId> = self.children(parent).collect();
+        for &child in &removed {
+            self.unlink(child);
+        }
+        for &node in &nodes {
+            self.link(parent, node, None);
+        }
+        if !removed.is_empty() || !nodes.is_empty() {
+            self.queue_child_list(parent, nodes, removed, None, None);
+        }
+    }
+
+    // take a node out of its parent without telling the observers
+    fn unlink(&mut self, id: NodeId) {
+        let parent = match self.parent(id) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if self.is_connected(parent) {
+            self.index_ids(id, false);
+        }
+        let (previous, next) = (self.previous_sibling(id), self.next_sibling(id));
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = next,
+            None => self.node_mut(parent).first_child = next,
+        }
+        match next {
+            Some(next) => self.node_mut(next).previous_sibling = previous,
+            None => self.node_mut(parent).last_child = previous,
+        }
+        let node = self.node_mut(id);
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    // whether `child` may become a child of `parent`, before `reference` or
+    // in place of `replaced`: a document holds at most one doctype and then
+    // at most one element, and no text, and only a document holds a doctype
+    fn check_insert(&self, parent: NodeId, child: NodeId, reference: Option<NodeId>, replaced: Option<NodeId>) -> DomResult<()> {
+        let parent_holds_children = matches!(self.node_type(parent), NodeType::Element(_) | NodeType::Document(_));
+        let is_document = matches!(self.node_type(child), NodeType::Document(_));
+        if !parent_holds_children || is_document || child == self.root || self.contains(child, parent) {
+            return Err(DomError::HierarchyRequest);
+        }
+        let parent_is_document = matches!(self.node_type(parent), NodeType::Document(_));
+        let allowed = match self.node_type(child) {
+            NodeType::Doctype(_) if !parent_is_document => false,
+            _ if !parent_is_document => true,
+            NodeType::Text(_) | NodeType::CData(_) => false,
+            child_type => {
+                // the other children, split around where the node goes
+                let children: Vec<NodeId> = self.children(parent).filter(|&other| Some(other) != replaced).collect();
+                let split = match replaced.or(reference) {
+                    Some(anchor) => self.children(parent).take_while(|&other| other != anchor).count(),
+                    None => children.len(),
+                };
+                let (before, after) = children.split_at(split.min(children.len()));
+                let is_element = |&id: &NodeId| self.element(id).is_some();
+                let is_doctype = |&id: &NodeId| matches!(self.node_type(id), NodeType::Doctype(_));
+                match child_type {
+                    NodeType::Element(_) => !children.iter().any(is_element) && !after.iter().any(is_doctype),
+                    NodeType::Doctype(_) => !children.iter().any(is_doctype) && !before.iter().any(is_element),
+                    _ => true,
+                }
+            }
+        };
+        if !allowed {
+            return Err(DomError::HierarchyRequest);
+        }
+        Ok(())
+    }
+
+    // put a node that has no parent into `parent`, before `reference` or last
+    fn link(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        let previous = match reference {
+            Some(reference) => self.previous_sibling(reference),
+            None => self.last_child(parent),
+        };
+        {
+            let node = self.node_mut(child);
+            node.parent = Some(parent);
+            node.previous_sibling = previous;
+            node.next_sibling = reference;
+        }
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = Some(child),
+            None => self.node_mut(parent).first_child = Some(child),
+        }
+        match reference {
+            Some(reference) => self.node_mut(reference).previous_sibling = Some(child),
+            None => self.node_mut(parent).last_child = Some(child),
+        }
+        if self.is_connected(parent) {
+            self.index_ids(child, true);
+        }
+    }
+
+    // add the ids of the elements under `id` to the index, or remove them
+    fn index_ids(&mut self, id: NodeId, add: bool) {
+        let elements: Vec<(NodeId, String)> = self.preorder(id)
+            .filter_map(|node| self.element(node)?.id().map(|value| (node, value.clone())))
+            .collect();
+        for (node, value) in elements {
+            if add {
+                self.index_id(node, value);
+            } else {
+                self.unindex_id(node, &value);
+            }
+        }
+    }
+
+    fn index_id(&mut self, id: NodeId, value: String) {
+        // an empty id matches nothing
+        if !value.is_empty() {
+            self.ids.entry(value).or_default().push(id);
+        }
+    }
+
+    fn unindex_id(&mut self, id: NodeId, value: &str) {
+        if let Some(ids) = self.ids.get_mut(value) {
+            ids.retain(|&node| node != id);
+            if ids.is_empty() {
+                self.ids.remove(value);
+            }
+        }
+    }
+
+    /// Whether the node is in the tree, under the root or the root itself.
+    pub fn is_connected(&self, id: NodeId) -> bool {
+        self.contains(self.root, id)
+    }
+
+    /// The element in the tree whose id is `value`, the first in document
+    /// order if several have it. Only when several do is the tree searched.
+    pub fn get_element_by_id(&self, value: &str) -> Option<NodeId> {
+        match &self.ids.get(value)?[..] {
+            [id] => Some(*id),
+            ids => self.preorder(self.root).find(|node| ids.contains(node)),
+        }
+    }
+
+    /// Whether `other` is `id` or one of its descendants.
+    pub fn contains(&self, id: NodeId, other: NodeId) -> bool {
+        std::iter::successors(Some(other), |&node| self.parent(node)).any(|node| node == id)
+    }
+
+    /// A copy of the node that isn't in the tree, with copies of all its
+    /// descendants when `deep`.
+    pub fn clone_node(&mut self, id: NodeId, deep: bool) -> NodeId {
+        let copy = self.create_node(self.node_type(id).clone());
+        self.node_mut(copy).source = self.source(id);
+        if deep {
+            let children: Vec<NodeId> = self.children(id).collect();
+            for child in children {
+                let child_copy = self.clone_node(child, true);
+                self.link(copy, child_copy, None);
+            }
+        }
+        copy
+    }
+
+    /// Replace the children of an element with a single text node, or none
+    /// for empty text, or set the data of text, comments, cdata and
+    /// processing instructions. Documents and doctypes are left as they are.
+    pub fn set_text_content(&mut self, id: NodeId, text: &str) {
+        let old_value = match self.node_type_mut(id) {
+            NodeType::Text(data) | NodeType::Comment(data) | NodeType::CData(data)
+                | NodeType::ProcessingInstruction(_, data) => std::mem::replace(data, text.to_string()),
+            NodeType::Doctype(_) | NodeType::Document(_) => return,
+            NodeType::Element(_) => {
+                let nodes = if text.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![self.create_node(NodeType::Text(text.to_string()))]
+                };
+                self.replace_all(id, nodes);
+                return;
+            }
+        };
+        self.queue_character_data(id, old_value);
+    }
+
+    /// Set an attribute of an element, keeping its place if it was set before.
+    /// Nodes other than elements are left as they are.
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: &str) {
+        let old_value = match self.node_type_mut(id) {
+            NodeType::Element(elem) => {
+                let old_value = elem.attributes.insert(name.to_string(), value.to_string());
+                // the new value wasn't parsed from anywhere
+                if old_value.as_deref() != Some(value) {
+                    elem.attribute_sources.remove(name);
+                }
+                old_value
+            }
+            _ => return,
+        };
+        if name == "id" && self.is_connected(id) {
+            if let Some(old_value) = &old_value {
+                self.unindex_id(id, old_value);
+            }
+            self.index_id(id, value.to_string());
+        }
+        self.queue_attribute(id, name, old_value);
+    }
+
+    /// Remove an attribute of an element, giving back its value.
+    pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> Option<String> {
+        let old_value = match self.node_type_mut(id) {
+            NodeType::Element(elem) => {
+                elem.attribute_sources.remove(name);
+                elem.attribute_namespaces.remove(name);
+                // the others keep their order
+                elem.attributes.shift_remove(name)?
+            }
+            _ => return None,
+        };
+        if name == "id" && self.is_connected(id) {
+            self.unindex_id(id, &old_value);
+        }
+        self.queue_attribute(id, name, Some(old_value.clone()));
+        Some(old_value)
+    }
+
+    /// Drop the empty text nodes under `id` and merge adjacent ones.
+    pub fn normalize(&mut self, id: NodeId) {
+        let mut child = self.first_child(id);
+        while let Some(current) = child {
+            child = self.next_sibling(current);
+            let text = match self.node_type(current) {
+                NodeType::Text(text) => text.clone(),
+                _ => {
+                    self.normalize(current);
+                    continue;
+                }
+            };
+            let mut merged = text;
+            let mut source = self.source(current);
+            let mut merges = false;
+            while let Some(next) = child {
+                match self.node_type(next) {
+                    NodeType::Text(text) => merged.push_str(text),
+                    _ => break,
+                }
+                // the merged text spans the input of all the nodes
+                source = source.zip(self.source(next)).map(|(start, end)| SourceRange { start: start.start, end: end.end });
+                child = self.next_sibling(next);
+                self.detach(next);
+                merges = true;
+            }
+            if merged.is_empty() {
+                self.detach(current);
+            } else if merges {
+                self.set_text_content(current, &merged);
+                self.node_mut(current).source = source;
+            }
+        }
+    }
+
+    pub fn node_type(&self, id: NodeId) -> &NodeType {
+        &self.node(id).node_type
+    }
+
+    // changes go through the methods above, which keep the id index right
+    fn node_type_mut(&mut self, id: NodeId) -> &mut NodeType {
+        &mut self.node_mut(id).node_type
+    }
+
+    /// The element data of `id`, None if it isn't an element.
+    pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+        match &self.node(id).node_type {
+            NodeType::Element(elem) => Some(elem),
+            _ => None,
+        }
+    }
+
+    /// Where the node was found in the parsed input, see `Node::source`.
+    pub fn source(&self, id: NodeId) -> Option<SourceRange> {
+        self.node(id).source
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).previous_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).next_sibling
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).last_child
+    }
+
+    /// The children of `id`, first to last.
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children { document: self, next: self.first_child(id) }
+    }
+}
+
+pub struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.next_sibling(id);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::{comment, elem, text, AttrMap};
+    use crate::dom::tests::parse;
+
+    #[test]
+    fn test_links() {
+        //<div><p>a</p>b<!--c--></div>
+        let p = elem("p".to_owned(), AttrMap::new(), vec![text("a".to_owned())]);
+        let div = elem("div".to_owned(), AttrMap::new(), vec![p, text("b".to_owned()), comment("c".to_owned())]);
+        let document = Document::from_node(div);
+
+        let div = document.root();
+        let children: Vec<NodeId> = document.children(div).collect();
+        assert_eq!(children.len(), 3);
+        let (p, b, c) = (children[0], children[1], children[2]);
+        assert_eq!(document.element(p).map(|elem| elem.tag_name.as_str()), Some("p"));
+        assert_eq!((document.first_child(div), document.last_child(div)), (Some(p), Some(c)));
+        assert_eq!((document.previous_sibling(b), document.next_sibling(b)), (Some(p), Some(c)));
+        assert_eq!((document.previous_sibling(p), document.next_sibling(c)), (None, None));
+        assert!(children.iter().all(|&child| document.parent(child) == Some(div)));
+        assert_eq!(document.parent(div), None);
+
+        let a = document.first_child(p).unwrap();
+        assert!(matches!(document.node_type(a), NodeType::Text(text) if text == "a"));
+        assert_eq!(document.parent(a), Some(p));
+        assert_eq!(document.children(a).count(), 0);
+
+        // and back to the owned tree
+        assert_eq!(document.to_node(div).to_string(), "<div><p>a</p>b<!--c--></div>");
+        assert_eq!(document.to_node(p).to_string(), "<p>a</p>");
+    }
+
+    #[test]
+    fn test_append_child() {
+        let mut document = Document::from_node(elem("ul".to_owned(), AttrMap::new(), Vec::new()));
+        let ul = document.root();
+        let a = document.create_node(NodeType::Text("a".to_owned()));
+        assert_eq!(document.parent(a), None);
+        document.append_child(ul, a).unwrap();
+        let b = document.create_node(NodeType::Text("b".to_owned()));
+        document.append_child(ul, b).unwrap();
+        assert_eq!(document.children(ul).collect::<Vec<NodeId>>(), vec![a, b]);
+        assert_eq!((document.next_sibling(a), document.previous_sibling(b)), (Some(b), Some(a)));
+        assert_eq!(document.to_node(ul).to_string(), "<ul>ab</ul>");
+    }
+
+    // the child of `parent` at `index`
+    fn child(document: &Document, parent: NodeId, index: usize) -> NodeId {
+        document.children(parent).nth(index).unwrap()
+    }
+
+    fn markup(document: &Document) -> String {
+        document.to_node(document.root()).to_string()
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut document = parse("<ul><li>a</li><li>b</li><li>c</li></ul>");
+        let ul = document.root();
+        let (a, b, c) = (child(&document, ul, 0), child(&document, ul, 1), child(&document, ul, 2));
+
+        // moving a node takes it out of where it was
+        document.insert_before(ul, c, Some(a)).unwrap();
+        assert_eq!(markup(&document), "<ul><li>c</li><li>a</li><li>b</li></ul>");
+        document.insert_before(ul, b, Some(b)).unwrap();
+        assert_eq!(markup(&document), "<ul><li>c</li><li>a</li><li>b</li></ul>");
+        document.append_child(ul, c).unwrap();
+        assert_eq!(markup(&document), "<ul><li>a</li><li>b</li><li>c</li></ul>");
+        assert_eq!((document.first_child(ul), document.last_child(ul)), (Some(a), Some(c)));
+
+        document.remove_child(ul, b).unwrap();
+        assert_eq!(markup(&document), "<ul><li>a</li><li>c</li></ul>");
+        assert_eq!((document.parent(b), document.next_sibling(a), document.previous_sibling(c)), (None, Some(c), Some(a)));
+        assert_eq!(document.remove_child(ul, b), Err(DomError::NotFound));
+        // a removed node can go back in, and its children come along
+        document.insert_before(ul, b, Some(c)).unwrap();
+        assert_eq!(markup(&document), "<ul><li>a</li><li>b</li><li>c</li></ul>");
+
+        let text = document.first_child(a).unwrap();
+        assert_eq!(document.insert_before(ul, b, Some(text)), Err(DomError::NotFound));
+        document.detach(a);
+        document.detach(a);
+        assert_eq!(markup(&document), "<ul><li>b</li><li>c</li></ul>");
+    }
+
+    #[test]
+    fn test_moving_into_own_subtree() {
+        let mut document = parse("<div><p><b>x</b></p></div>");
+        let div = document.root();
+        let p = child(&document, div, 0);
+        let b = child(&document, p, 0);
+        let x = child(&document, b, 0);
+
+        assert_eq!(document.append_child(b, p), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(p, p), Err(DomError::HierarchyRequest));
+        assert_eq!(document.insert_before(b, div, None), Err(DomError::HierarchyRequest));
+        assert_eq!(document.replace_child(b, p, x), Err(DomError::HierarchyRequest));
+        // text can't have children
+        let y = document.create_node(NodeType::Text("y".to_owned()));
+        assert_eq!(document.append_child(x, y), Err(DomError::HierarchyRequest));
+        // nothing changed
+        assert_eq!(markup(&document), "<div><p><b>x</b></p></div>");
+
+        // but a node can move up out of its parent, or into a detached subtree
+        document.append_child(div, b).unwrap();
+        assert_eq!(markup(&document), "<div><p></p><b>x</b></div>");
+        document.detach(p);
+        document.append_child(p, b).unwrap();
+        assert_eq!(document.append_child(b, p), Err(DomError::HierarchyRequest));
+        assert_eq!(markup(&document), "<div></div>");
+        assert!(document.contains(p, x) && !document.contains(div, x));
+    }
+
+    #[test]
+    fn test_document_children() {
+        let mut document = crate::html::Parser::new("<!DOCTYPE html><!--c--><html></html>").parse_tree().unwrap();
+        let root = document.root();
+        let (doctype, comment, html) = (child(&document, root, 0), child(&document, root, 1), child(&document, root, 2));
+        let text = document.create_node(NodeType::Text("t".to_owned()));
+        let body = document.create_node(document.node_type(html).clone());
+        let other_doctype = document.clone_node(doctype, false);
+
+        // no text, nor a second element or doctype, in a document
+        assert_eq!(document.append_child(root, text), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(root, body), Err(DomError::HierarchyRequest));
+        assert_eq!(document.insert_before(root, other_doctype, Some(doctype)), Err(DomError::HierarchyRequest));
+        // no doctype in an element
+        assert_eq!(document.append_child(html, other_doctype), Err(DomError::HierarchyRequest));
+        // the element comes after the doctype
+        assert_eq!(document.replace_child(root, body, doctype), Err(DomError::HierarchyRequest));
+        document.detach(html);
+        assert_eq!(document.insert_before(root, body, Some(comment)), Ok(()));
+        document.detach(doctype);
+        assert_eq!(document.append_child(root, doctype), Err(DomError::HierarchyRequest));
+        // but either can take the place of one of its kind
+        document.replace_child(root, html, body).unwrap();
+        document.insert_before(root, doctype, Some(html)).unwrap();
+        document.replace_child(root, other_doctype, doctype).unwrap();
+        assert_eq!(markup(&document), "<!DOCTYPE html><html></html><!--c-->");
+        // nor does text replace them
+        document.set_text_content(root, "x");
+        assert_eq!(markup(&document), "<!DOCTYPE html><html></html><!--c-->");
+    }
+
+    #[test]
+    fn test_replace_child() {
+        let mut document = parse("<p><i>a</i><b>b</b>c</p>");
+        let p = document.root();
+        let (i, b, c) = (child(&document, p, 0), child(&document, p, 1), child(&document, p, 2));
+        document.replace_child(p, c, i).unwrap();
+        assert_eq!(markup(&document), "<p>c<b>b</b></p>");
+        assert_eq!(document.parent(i), None);
+        document.replace_child(p, b, b).unwrap();
+        // replacing a node with its next sibling
+        document.replace_child(p, b, c).unwrap();
+        assert_eq!(markup(&document), "<p><b>b</b></p>");
+        assert_eq!(document.replace_child(p, i, c), Err(DomError::NotFound));
+    }
+
+    #[test]
+    fn test_clone_node() {
+        let mut document = parse("<div id=a class=b><p>x</p>y</div>");
+        let div = document.root();
+        let shallow = document.clone_node(div, false);
+        assert_eq!(document.to_node(shallow).to_string(), "<div id=\"a\" class=\"b\"></div>");
+        let deep = document.clone_node(div, true);
+        assert_eq!(document.to_node(deep).to_string(), "<div id=\"a\" class=\"b\"><p>x</p>y</div>");
+        assert_eq!(document.parent(deep), None);
+
+        // the copy is a tree of its own
+        let p = child(&document, div, 0);
+        document.set_text_content(p, "changed");
+        assert_eq!(document.to_node(deep).to_string(), "<div id=\"a\" class=\"b\"><p>x</p>y</div>");
+        document.append_child(div, deep).unwrap();
+        assert_eq!(document.children(div).count(), 3);
+    }
+
+    #[test]
+    fn test_text_content_and_attributes() {
+        let mut document = parse("<p title=t id=i lang=en>a<b>b</b><!--c--></p>");
+        let p = document.root();
+        assert!(document.to_node(p).attribute_source("title").is_some());
+        document.set_attribute(p, "lang", "en");
+        assert!(document.to_node(p).attribute_source("lang").is_some());
+        let comment = child(&document, p, 2);
+        document.set_text_content(comment, "d");
+        assert_eq!(markup(&document), "<p title=\"t\" id=\"i\" lang=\"en\">a<b>b</b><!--d--></p>");
+
+        assert_eq!(document.remove_attribute(p, "id"), Some("i".to_owned()));
+        assert_eq!(document.remove_attribute(p, "id"), None);
+        assert_eq!(document.remove_attribute(comment, "id"), None);
+        document.set_attribute(p, "title", "u");
+        document.set_attribute(p, "dir", "ltr");
+        assert_eq!(document.to_node(p).attribute_source("id"), None);
+        assert_eq!(document.to_node(p).attribute_source("title"), None);
+        document.set_text_content(p, "<text>");
+        assert_eq!(markup(&document), "<p title=\"u\" lang=\"en\" dir=\"ltr\">&lt;text&gt;</p>");
+        document.set_text_content(p, "");
+        assert_eq!(document.first_child(p), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut document = parse("<div>a<p>b</p>c</div>");
+        let div = document.root();
+        let p = child(&document, div, 1);
+        let a = child(&document, div, 0);
+        for text in &["", "d", ""] {
+            let node = document.create_node(NodeType::Text(text.to_string()));
+            document.insert_before(div, node, Some(p)).unwrap();
+        }
+        let empty = document.create_node(NodeType::Text(String::new()));
+        document.append_child(p, empty).unwrap();
+        let e = document.create_node(NodeType::Text("e".to_owned()));
+        document.append_child(p, e).unwrap();
+        assert_eq!(document.children(div).count(), 6);
+
+        document.normalize(div);
+        assert_eq!(document.children(div).count(), 3);
+        assert_eq!(document.first_child(div), Some(a));
+        assert!(matches!(document.node_type(a), NodeType::Text(text) if text == "ad"));
+        assert_eq!(document.children(p).count(), 1);
+        assert_eq!(markup(&document), "<div>ad<p>be</p>c</div>");
+        // "d" wasn't parsed, so the merged text wasn't written anywhere
+        assert_eq!(document.source(a), None);
+        assert!(document.source(child(&document, div, 2)).is_some());
+    }
+
+    fn id_of(document: &Document, value: &str) -> Option<String> {
+        document.get_element_by_id(value).map(|id| document.element(id).unwrap().tag_name.clone())
+    }
+
+    #[test]
+    fn test_id_index() {
+        let mut document = parse("<div><p id=a>1</p><b id=a>2</b><i id=c><u id=d></u></i></div>");
+        let div = document.root();
+        let (p, b, i) = (child(&document, div, 0), child(&document, div, 1), child(&document, div, 2));
+        assert_eq!(id_of(&document, "a"), Some("p".to_owned()));
+
+        // moving changes which of the two comes first
+        document.append_child(div, p).unwrap();
+        assert_eq!(id_of(&document, "a"), Some("b".to_owned()));
+        document.remove_child(div, b).unwrap();
+        assert_eq!(id_of(&document, "a"), Some("p".to_owned()));
+        document.detach(p);
+        assert_eq!(id_of(&document, "a"), None);
+
+        // detached nodes aren't found, nor anything under them
+        document.detach(i);
+        assert_eq!((id_of(&document, "c"), id_of(&document, "d")), (None, None));
+        document.set_attribute(i, "id", "e");
+        assert_eq!(id_of(&document, "e"), None);
+        document.append_child(div, i).unwrap();
+        assert_eq!((id_of(&document, "e"), id_of(&document, "d")), (Some("i".to_owned()), Some("u".to_owned())));
+
+        document.set_attribute(i, "id", "f");
+        assert_eq!((id_of(&document, "e"), id_of(&document, "f")), (None, Some("i".to_owned())));
+        document.remove_attribute(i, "id");
+        assert_eq!(id_of(&document, "f"), None);
+        document.set_attribute(i, "id", "");
+        assert_eq!(id_of(&document, ""), None);
+
+        // copies get into the index once they are in the tree
+        let copy = document.clone_node(i, true);
+        assert_eq!(document.get_element_by_id("d"), document.first_child(i));
+        document.insert_before(div, copy, Some(i)).unwrap();
+        assert_eq!(document.get_element_by_id("d"), document.first_child(copy));
+        document.set_text_content(div, "gone");
+        assert_eq!(id_of(&document, "d"), None);
+    }
+}
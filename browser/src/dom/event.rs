@@ -0,0 +1,404 @@
+// Events sent through the tree, to listeners on the way down to the target,
+// at it, and on the way back up
+// https://dom.spec.whatwg.org/#concept-event-dispatch
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use super::{Document, NodeId};
+
+/// Where an event is on its way through the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+    // not being dispatched
+    None,
+    // on the way down from the root to the target
+    Capturing,
+    AtTarget,
+    // on the way back up from the target to the root
+    Bubbling,
+}
+
+/// What the built-in events carry besides their type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventDetail {
+    None,
+    // the button pressed, 0 for the main one, and where the pointer was
+    Mouse { button: i16, client_x: f32, client_y: f32 },
+    // the text inserted, None when the change inserted none
+    Input { data: Option<String> },
+    // the button that submitted the form, None when it was submitted another way
+    Submit { submitter: Option<NodeId> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    event_type: String,
+    bubbles: bool,
+    cancelable: bool,
+    detail: EventDetail,
+    target: Option<NodeId>,
+    current_target: Option<NodeId>,
+    phase: EventPhase,
+    propagation_stopped: bool,
+    immediate_propagation_stopped: bool,
+    default_prevented: bool,
+}
+
+impl Event {
+    /// An event of any type with no detail.
+    pub fn new(event_type: &str, bubbles: bool, cancelable: bool) -> Event {
+        Event {
+            event_type: event_type.to_string(),
+            bubbles,
+            cancelable,
+            detail: EventDetail::None,
+            target: None,
+            current_target: None,
+            phase: EventPhase::None,
+            propagation_stopped: false,
+            immediate_propagation_stopped: false,
+            default_prevented: false,
+        }
+    }
+
+    /// A click of the main button, which bubbles and can be canceled.
+    pub fn click() -> Event {
+        Event { detail: EventDetail::Mouse { button: 0, client_x: 0.0, client_y: 0.0 }, ..Event::new("click", true, true) }
+    }
+
+    /// The event sent after the value of a form control changed, which
+    /// bubbles but can't be canceled.
+    pub fn input(data: Option<&str>) -> Event {
+        Event { detail: EventDetail::Input { data: data.map(str::to_string) }, ..Event::new("input", true, false) }
+    }
+
+    /// The event sent to a form before it is submitted, which bubbles and
+    /// can be canceled.
+    pub fn submit(submitter: Option<NodeId>) -> Event {
+        Event { detail: EventDetail::Submit { submitter }, ..Event::new("submit", true, true) }
+    }
+
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    pub fn bubbles(&self) -> bool {
+        self.bubbles
+    }
+
+    pub fn cancelable(&self) -> bool {
+        self.cancelable
+    }
+
+    pub fn detail(&self) -> &EventDetail {
+        &self.detail
+    }
+
+    /// The node the event was dispatched at.
+    pub fn target(&self) -> Option<NodeId> {
+        self.target
+    }
+
+    /// The node whose listener is being called.
+    pub fn current_target(&self) -> Option<NodeId> {
+        self.current_target
+    }
+
+    pub fn phase(&self) -> EventPhase {
+        self.phase
+    }
+
+    /// Let no node after the current one get the event.
+    pub fn stop_propagation(&mut self) {
+        self.propagation_stopped = true;
+    }
+
+    /// Let no listener after the current one get the event, even on the
+    /// same node.
+    pub fn stop_immediate_propagation(&mut self) {
+        self.propagation_stopped = true;
+        self.immediate_propagation_stopped = true;
+    }
+
+    /// Cancel what the event would do by default, if it can be canceled.
+    pub fn prevent_default(&mut self) {
+        if self.cancelable {
+            self.default_prevented = true;
+        }
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.default_prevented
+    }
+}
+
+/// A listener added to a node, to remove it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(usize);
+
+type Callback = Rc<dyn Fn(&mut Document, &mut Event)>;
+
+#[derive(Clone)]
+struct Listener {
+    id: ListenerId,
+    event_type: String,
+    capture: bool,
+    callback: Callback,
+}
+
+impl Debug for Listener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener").field("id", &self.id).field("event_type", &self.event_type)
+            .field("capture", &self.capture).finish()
+    }
+}
+
+/// The listeners of the nodes of a document, in the order they were added.
+#[derive(Debug, Default)]
+pub(super) struct EventListeners {
+    by_node: HashMap<NodeId, Vec<Listener>>,
+    next_id: usize,
+}
+
+impl EventListeners {
+    fn contains(&self, node: NodeId, id: ListenerId) -> bool {
+        self.by_node.get(&node).is_some_and(|listeners| listeners.iter().any(|listener| listener.id == id))
+    }
+}
+
+impl Document {
+    /// Call `listener` with every event of `event_type` that reaches the
+    /// node, while it goes down to its target when `capture` and otherwise
+    /// at the target and while it bubbles up. The listener gets the document
+    /// too, so it can change the tree or dispatch other events, even ones
+    /// that come back to it. State it keeps goes in a `Cell` or a `RefCell`.
+    pub fn add_event_listener<F>(&mut self, id: NodeId, event_type: &str, capture: bool, listener: F) -> ListenerId
+        where F: Fn(&mut Document, &mut Event) + 'static
+    {
+        let listener_id = ListenerId(self.listeners.next_id);
+        self.listeners.next_id += 1;
+        self.listeners.by_node.entry(id).or_default().push(Listener {
+            id: listener_id,
+            event_type: event_type.to_string(),
+            capture,
+            callback: Rc::new(listener),
+        });
+        listener_id
+    }
+
+    /// Remove a listener of the node, even while an event is being
+    /// dispatched, in which case it isn't called any more.
+    pub fn remove_event_listener(&mut self, id: NodeId, listener: ListenerId) {
+        if let Some(listeners) = self.listeners.by_node.get_mut(&id) {
+            listeners.retain(|added| added.id != listener);
+        }
+    }
+
+    /// Send `event` to `target`: to the capturing listeners of its ancestors
+    /// from the root down, to the listeners of the target, then if the event
+    /// bubbles to the other listeners of the ancestors back up. Gives false
+    /// when a listener prevented the default.
+    pub fn dispatch_event(&mut self, target: NodeId, event: &mut Event) -> bool {
+        let ancestors: Vec<NodeId> = self.ancestors(target).collect();
+        event.target = Some(target);
+
+        for &node in ancestors.iter().rev() {
+            self.invoke(node, event, EventPhase::Capturing);
+        }
+        self.invoke(target, event, EventPhase::AtTarget);
+        if event.bubbles {
+            for &node in &ancestors {
+                self.invoke(node, event, EventPhase::Bubbling);
+            }
+        }
+
+        event.phase = EventPhase::None;
+        event.current_target = None;
+        event.propagation_stopped = false;
+        event.immediate_propagation_stopped = false;
+        !event.default_prevented
+    }
+
+    // call the listeners of `node` that take the event in `phase`
+    fn invoke(&mut self, node: NodeId, event: &mut Event, phase: EventPhase) {
+        if event.propagation_stopped {
+            return;
+        }
+        event.phase = phase;
+        event.current_target = Some(node);
+        // the listeners added by these ones wait for the next event
+        let listeners: Vec<Listener> = match self.listeners.by_node.get(&node) {
+            Some(listeners) => listeners.iter()
+                .filter(|listener| listener.event_type == event.event_type)
+                .filter(|listener| match phase {
+                    EventPhase::Capturing => listener.capture,
+                    EventPhase::Bubbling => !listener.capture,
+                    _ => true,
+                })
+                .cloned()
+                .collect(),
+            None => return,
+        };
+        // capturing listeners of the target come first, like the others
+        let (capturing, others): (Vec<Listener>, Vec<Listener>) = listeners.into_iter().partition(|listener| listener.capture);
+        for listener in capturing.into_iter().chain(others) {
+            if event.immediate_propagation_stopped {
+                break;
+            }
+            if !self.listeners.contains(node, listener.id) {
+                continue;
+            }
+            (listener.callback)(self, event);
+        }
+    }
+
+    /// Click the node the way a user would: dispatch a click at it, then
+    /// unless that was canceled, submit the form of the submit button the
+    /// click landed in. Gives false when the click was canceled.
+    pub fn click(&mut self, id: NodeId) -> bool {
+        if !self.dispatch_event(id, &mut Event::click()) {
+            return false;
+        }
+        let button = std::iter::once(id).chain(self.ancestors(id)).find(|&node| self.is_submit_button(node));
+        let form = button.and_then(|button| self.ancestors(button).find(|&node| self.is_html_element(node, "form")));
+        if let Some(form) = form {
+            self.dispatch_event(form, &mut Event::submit(button));
+        }
+        true
+    }
+
+    fn is_html_element(&self, id: NodeId, tag_name: &str) -> bool {
+        self.element(id).is_some_and(|elem| elem.is_html_element(tag_name))
+    }
+
+    // a <button> of the submit type, the default, or an <input type=submit|image>
+    fn is_submit_button(&self, id: NodeId) -> bool {
+        let kind = self.element(id).and_then(|elem| elem.attributes.get("type")).map(|kind| kind.to_ascii_lowercase());
+        if self.is_html_element(id, "button") {
+            !matches!(kind.as_deref(), Some("reset") | Some("button"))
+        } else {
+            self.is_html_element(id, "input") && matches!(kind.as_deref(), Some("submit") | Some("image"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::tests::parse;
+    use std::cell::{Cell, RefCell};
+
+    // a listener that writes where it was called to `log`
+    fn logger(log: &Rc<RefCell<Vec<String>>>, name: &'static str) -> impl Fn(&mut Document, &mut Event) + 'static {
+        let log = Rc::clone(log);
+        move |_, event| log.borrow_mut().push(format!("{} {:?}", name, event.phase()))
+    }
+
+    #[test]
+    fn test_phases() {
+        let mut document = parse("<div><p><b>text</b></p></div>");
+        let div = document.root();
+        let p = document.first_child(div).unwrap();
+        let b = document.first_child(p).unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        document.add_event_listener(div, "click", false, logger(&log, "div"));
+        document.add_event_listener(div, "click", true, logger(&log, "div"));
+        document.add_event_listener(p, "click", true, logger(&log, "p"));
+        document.add_event_listener(b, "click", false, logger(&log, "b"));
+        document.add_event_listener(b, "click", true, logger(&log, "b capturing"));
+        document.add_event_listener(p, "input", false, logger(&log, "input"));
+
+        let mut event = Event::click();
+        assert!(document.dispatch_event(b, &mut event));
+        assert_eq!(*log.borrow(), vec!["div Capturing", "p Capturing", "b capturing AtTarget", "b AtTarget", "div Bubbling"]);
+        assert_eq!((event.target(), event.current_target(), event.phase()), (Some(b), None, EventPhase::None));
+
+        // events that don't bubble stop at the target
+        log.borrow_mut().clear();
+        document.dispatch_event(b, &mut Event::new("click", false, false));
+        assert_eq!(*log.borrow(), vec!["div Capturing", "p Capturing", "b capturing AtTarget", "b AtTarget"]);
+        log.borrow_mut().clear();
+        document.dispatch_event(b, &mut Event::input(Some("x")));
+        assert_eq!(*log.borrow(), vec!["input Bubbling"]);
+    }
+
+    #[test]
+    fn test_dispatch_from_a_listener() {
+        let mut document = parse("<div><p>text</p></div>");
+        let div = document.root();
+        let p = document.first_child(div).unwrap();
+        let depth = Rc::new(Cell::new(0));
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&seen);
+        // a click on the div clicks the paragraph, which bubbles back to the
+        // same listener while the first click is still being dispatched
+        document.add_event_listener(div, "click", false, move |document, event| {
+            log.borrow_mut().push((depth.get(), event.target()));
+            if depth.get() < 2 {
+                depth.set(depth.get() + 1);
+                document.dispatch_event(p, &mut Event::click());
+            }
+        });
+        document.dispatch_event(div, &mut Event::click());
+        assert_eq!(*seen.borrow(), vec![(0, Some(div)), (1, Some(p)), (2, Some(p))]);
+    }
+
+    #[test]
+    fn test_stopping_and_canceling() {
+        let mut document = parse("<div><p>text</p></div>");
+        let div = document.root();
+        let p = document.first_child(div).unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let stopper = document.add_event_listener(p, "click", false, |_, event| {
+            event.stop_propagation();
+            event.prevent_default();
+        });
+        document.add_event_listener(p, "click", false, logger(&log, "p"));
+        document.add_event_listener(div, "click", false, logger(&log, "div"));
+
+        // the other listeners of the node are still called
+        assert!(!document.dispatch_event(p, &mut Event::click()));
+        assert_eq!(*log.borrow(), vec!["p AtTarget"]);
+        // input can't be canceled
+        assert!(document.dispatch_event(p, &mut Event::input(None)));
+
+        log.borrow_mut().clear();
+        document.remove_event_listener(p, stopper);
+        document.add_event_listener(p, "click", true, |_, event| event.stop_immediate_propagation());
+        assert!(document.dispatch_event(p, &mut Event::click()));
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_listeners_change_the_tree() {
+        let mut document = parse("<form><input name=q><button><span>go</span></button></form>");
+        let form = document.root();
+        let input = document.first_child(form).unwrap();
+        let button = document.next_sibling(input).unwrap();
+        let span = document.first_child(button).unwrap();
+        document.add_event_listener(input, "input", false, |document, event| {
+            let input = event.target().unwrap();
+            document.set_attribute(input, "value", "typed");
+        });
+        document.add_event_listener(form, "submit", false, |document, event| {
+            assert_eq!(event.detail(), &EventDetail::Submit { submitter: document.get_element_by_id("go") });
+            event.prevent_default();
+            let form = event.current_target().unwrap();
+            document.set_attribute(form, "class", "sent");
+        });
+        document.set_attribute(button, "id", "go");
+
+        document.dispatch_event(input, &mut Event::input(Some("typed")));
+        assert_eq!(document.element(input).unwrap().attributes.get("value").unwrap(), "typed");
+        assert!(document.click(span));
+        assert_eq!(document.element(form).unwrap().attributes.get("class").unwrap(), "sent");
+
+        // a canceled click submits nothing
+        document.remove_attribute(form, "class");
+        document.add_event_listener(button, "click", false, |_, event| event.prevent_default());
+        assert!(!document.click(span));
+        assert_eq!(document.element(form).unwrap().attributes.get("class"), None);
+    }
+}
@@ -0,0 +1,160 @@
+// What a document says about itself: its title, base url, encoding, and the
+// stylesheets and scripts it uses
+
+use super::{Document, ElementData, NodeId, NodeType};
+use crate::html;
+
+/// A stylesheet or a script of a document, from a url or written in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resource {
+    // the href of a <link rel=stylesheet> or the src of a <script>, as written
+    External { node: NodeId, url: String },
+    // the text of a <style> or of a <script> without a src
+    Inline { node: NodeId, text: String },
+}
+
+impl Document {
+    /// The root element, like <html>: the element child of the document
+    /// node, or the root itself when the tree was parsed without one.
+    pub fn document_element(&self) -> Option<NodeId> {
+        match self.node_type(self.root()) {
+            NodeType::Document(_) => self.children(self.root()).find(|&child| self.element(child).is_some()),
+            NodeType::Element(_) => Some(self.root()),
+            _ => None,
+        }
+    }
+
+    // the first child of the root element that is one of `tag_names`
+    fn html_child(&self, tag_names: &[&str]) -> Option<NodeId> {
+        let html = self.document_element().filter(|&html| self.element(html).unwrap().is_html_element("html"))?;
+        self.children(html)
+            .find(|&child| self.element(child).is_some_and(|elem| tag_names.iter().any(|name| elem.is_html_element(name))))
+    }
+
+    pub fn head(&self) -> Option<NodeId> {
+        self.html_child(&["head"])
+    }
+
+    /// The <body> of the document, or its <frameset>.
+    pub fn body(&self) -> Option<NodeId> {
+        self.html_child(&["body", "frameset"])
+    }
+
+    // the html elements named `tag_name` in document order
+    fn html_elements<'a>(&'a self, tag_name: &'a str) -> impl Iterator<Item = (NodeId, &'a ElementData)> + 'a {
+        self.preorder(self.root())
+            .filter_map(move |id| self.element(id).filter(|elem| elem.is_html_element(tag_name)).map(|elem| (id, elem)))
+    }
+
+    /// The text of the first <title>, with its white space collapsed and
+    /// trimmed. Empty if there's none.
+    pub fn title(&self) -> String {
+        match self.html_elements("title").next() {
+            Some((title, _)) => self.text_content(title).split_ascii_whitespace().collect::<Vec<&str>>().join(" "),
+            None => String::new(),
+        }
+    }
+
+    /// The href of the first <base> that has one, as written.
+    pub fn base_url(&self) -> Option<String> {
+        self.html_elements("base").find_map(|(_, elem)| elem.attributes.get("href").cloned())
+    }
+
+    /// Say which encoding the document was decoded from, like the one
+    /// `html::decode` found.
+    pub fn set_charset(&mut self, charset: &'static str) {
+        self.charset = Some(charset);
+    }
+
+    /// The name of the encoding of the document: the one it was decoded from
+    /// if that was set, else the one its first `<meta>` declaring one does,
+    /// else UTF-8.
+    pub fn charset(&self) -> &'static str {
+        self.charset
+            .or_else(|| self.html_elements("meta").find_map(|(_, elem)| html::meta_encoding(elem)))
+            .unwrap_or("UTF-8")
+    }
+
+    /// The stylesheets of the document in order: the hrefs of
+    /// `<link rel=stylesheet>` and the text of `<style>` elements.
+    pub fn stylesheets(&self) -> Vec<Resource> {
+        self.preorder(self.root())
+            .filter_map(|node| {
+                let elem = self.element(node)?;
+                if elem.is_html_element("style") {
+                    return Some(Resource::Inline { node, text: self.text_content(node) });
+                }
+                let is_stylesheet = elem.is_html_element("link") && elem.attributes.get("rel")
+                    .is_some_and(|rel| rel.split_ascii_whitespace().any(|kind| kind.eq_ignore_ascii_case("stylesheet")));
+                match elem.attributes.get("href") {
+                    Some(url) if is_stylesheet && !url.is_empty() => Some(Resource::External { node, url: url.clone() }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The scripts of the document in order: the src of `<script src>` and
+    /// the text of the others.
+    pub fn scripts(&self) -> Vec<Resource> {
+        self.html_elements("script")
+            .map(|(node, elem)| match elem.attributes.get("src") {
+                Some(url) => Resource::External { node, url: url.clone() },
+                None => Resource::Inline { node, text: self.text_content(node) },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    const SOURCE: &str = "<!DOCTYPE html><html><head>\n<meta http-equiv=Content-Type content='text/html; charset=latin1'>\n\
+        <title>\n  Let's build\n  a browser  </title><base href=/docs/><base href=/other/>\n\
+        <link rel='alternate STYLESHEET' href=a.css><link rel=icon href=i.png><style>p { color: red }</style>\n\
+        <script src=app.js></script></head><body><svg><title>not this</title><script>no</script></svg>\n\
+        <script>run()</script></body></html>";
+
+    #[test]
+    fn test_accessors() {
+        let document = Parser::new(SOURCE).tolerant().parse_tree().unwrap();
+        let html = document.document_element().unwrap();
+        assert_eq!(document.element(html).unwrap().tag_name, "html");
+        let name = |id: Option<NodeId>| id.map(|id| document.element(id).unwrap().tag_name.clone());
+        assert_eq!(name(document.head()), Some("head".to_owned()));
+        assert_eq!(name(document.body()), Some("body".to_owned()));
+        assert_eq!(document.title(), "Let's build a browser");
+        assert_eq!(document.base_url(), Some("/docs/".to_owned()));
+        assert_eq!(document.charset(), "windows-1252");
+
+        let urls: Vec<String> = document.stylesheets().into_iter().map(|resource| match resource {
+            Resource::External { url, .. } => url,
+            Resource::Inline { text, .. } => text,
+        }).collect();
+        assert_eq!(urls, vec!["a.css", "p { color: red }"]);
+        let scripts = document.scripts();
+        assert_eq!(scripts.len(), 2);
+        assert!(matches!(&scripts[0], Resource::External { url, .. } if url == "app.js"));
+        assert!(matches!(&scripts[1], Resource::Inline { text, .. } if text == "run()"));
+    }
+
+    #[test]
+    fn test_missing_parts() {
+        let mut document = Parser::new("<div><meta charset=gb2312></div>").parse_tree().unwrap();
+        let div = document.document_element().unwrap();
+        assert_eq!(document.element(div).unwrap().tag_name, "div");
+        assert_eq!((document.head(), document.body(), document.base_url()), (None, None, None));
+        assert_eq!(document.title(), "");
+        assert_eq!(document.charset(), "GBK");
+        // what the bytes were decoded from wins
+        document.set_charset("UTF-8");
+        assert_eq!(document.charset(), "UTF-8");
+        assert!(document.stylesheets().is_empty() && document.scripts().is_empty());
+
+        let document = Parser::new("<p>text</p>").tolerant().parse_tree().unwrap();
+        assert_eq!(document.charset(), "UTF-8");
+        assert!(document.head().is_some() && document.body().is_some());
+    }
+}
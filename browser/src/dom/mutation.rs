@@ -0,0 +1,427 @@
+// Records of the changes made to a tree, kept for the observers of the
+// nodes they were made to and handed over in batches
+// https://dom.spec.whatwg.org/#mutation-observers
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use super::{Document, DomError, DomResult, NodeId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationKind {
+    // children added or removed
+    ChildList,
+    Attributes,
+    // the data of a text, comment, cdata or processing instruction changed
+    CharacterData,
+}
+
+/// One change to the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutationRecord {
+    pub kind: MutationKind,
+    // the node whose children, attributes or data changed
+    pub target: NodeId,
+    pub added_nodes: Vec<NodeId>,
+    pub removed_nodes: Vec<NodeId>,
+    // the siblings around the added or removed nodes
+    pub previous_sibling: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+    pub attribute_name: Option<String>,
+    // the value of the attribute or the data before the change, when the
+    // observer asked for it and there was one
+    pub old_value: Option<String>,
+}
+
+/// Which changes an observer of a node gets records of.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MutationObserverInit {
+    pub child_list: bool,
+    pub attributes: bool,
+    pub character_data: bool,
+    // the changes to all the descendants of the node too
+    pub subtree: bool,
+    pub attribute_old_value: bool,
+    pub character_data_old_value: bool,
+    // only the attributes with these names, when set
+    pub attribute_filter: Option<Vec<String>>,
+}
+
+/// An observer of changes, to observe nodes with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObserverId(usize);
+
+type Callback = Rc<RefCell<dyn FnMut(&mut Document, Vec<MutationRecord>)>>;
+
+struct Observer {
+    callback: Callback,
+    records: Vec<MutationRecord>,
+}
+
+impl Debug for Observer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observer").field("records", &self.records).finish()
+    }
+}
+
+#[derive(Debug)]
+struct Registration {
+    observer: ObserverId,
+    options: MutationObserverInit,
+    // made when the node was removed from a subtree the observer observes,
+    // so it still hears of the node until it is next notified
+    transient: bool,
+}
+
+/// The observers of a document and the nodes each of them observes.
+#[derive(Debug, Default)]
+pub(super) struct MutationObservers {
+    // by id, so they are notified in the order they were created
+    observers: BTreeMap<ObserverId, Observer>,
+    registrations: HashMap<NodeId, Vec<Registration>>,
+    next_id: usize,
+}
+
+impl Document {
+    /// An observer that is given the records of the changes to the nodes it
+    /// observes by `notify_mutation_observers`.
+    pub fn create_mutation_observer<F>(&mut self, callback: F) -> ObserverId
+        where F: FnMut(&mut Document, Vec<MutationRecord>) + 'static
+    {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        self.observers.observers.insert(id, Observer { callback: Rc::new(RefCell::new(callback)), records: Vec::new() });
+        id
+    }
+
+    /// Have `observer` record the changes to `target` that `options` ask
+    /// for, in place of those it asked for before. Asking for old values or
+    /// filtering attributes asks for their kind of change too, and asking
+    /// for no kind of change is an error.
+    pub fn observe(&mut self, observer: ObserverId, target: NodeId, mut options: MutationObserverInit) -> DomResult<()> {
+        options.attributes |= options.attribute_old_value || options.attribute_filter.is_some();
+        options.character_data |= options.character_data_old_value;
+        if !(options.child_list || options.attributes || options.character_data)
+            || !self.observers.observers.contains_key(&observer) {
+            return Err(DomError::Type);
+        }
+        let registrations = self.observers.registrations.entry(target).or_default();
+        match registrations.iter_mut().find(|registration| registration.observer == observer && !registration.transient) {
+            Some(registration) => registration.options = options,
+            None => registrations.push(Registration { observer, options, transient: false }),
+        }
+        Ok(())
+    }
+
+    /// Stop `observer` from observing any node and drop the records it
+    /// wasn't given yet.
+    pub fn disconnect(&mut self, observer: ObserverId) {
+        self.unregister(observer, false);
+        if let Some(observer) = self.observers.observers.get_mut(&observer) {
+            observer.records.clear();
+        }
+    }
+
+    /// The records `observer` wasn't given yet, which it won't be given now.
+    pub fn take_records(&mut self, observer: ObserverId) -> Vec<MutationRecord> {
+        match self.observers.observers.get_mut(&observer) {
+            Some(observer) => std::mem::take(&mut observer.records),
+            None => Vec::new(),
+        }
+    }
+
+    /// Give each observer the records it has in one batch, in the order the
+    /// observers were created, until the callbacks make no more changes.
+    /// An observer stops hearing of the nodes removed from under it once it
+    /// is given its records.
+    /// Called from a callback, this leaves the records of the observers whose
+    /// callbacks are running queued, for the call that runs them to give.
+    pub fn notify_mutation_observers(&mut self) {
+        loop {
+            let pending = self.observers.observers.iter()
+                .find(|(_, observer)| !observer.records.is_empty() && observer.callback.try_borrow_mut().is_ok())
+                .map(|(&id, observer)| (id, Rc::clone(&observer.callback)));
+            let (id, callback) = match pending {
+                Some(pending) => pending,
+                None => break,
+            };
+            self.unregister(id, true);
+            let records = self.take_records(id);
+            (*callback.borrow_mut())(self, records);
+        }
+    }
+
+    // drop the transient registrations of `observer`, or all of them
+    fn unregister(&mut self, observer: ObserverId, transient_only: bool) {
+        self.observers.registrations.retain(|_, registrations| {
+            registrations.retain(|registration| {
+                registration.observer != observer || (transient_only && !registration.transient)
+            });
+            !registrations.is_empty()
+        });
+    }
+
+    pub(super) fn queue_child_list(&mut self, target: NodeId, added_nodes: Vec<NodeId>, removed_nodes: Vec<NodeId>,
+                                   previous_sibling: Option<NodeId>, next_sibling: Option<NodeId>) {
+        // the observers of the subtree the nodes were in keep hearing of them
+        // for now, for changes made to them right after they were removed
+        let transient: Vec<(ObserverId, MutationObserverInit)> = std::iter::once(target).chain(self.ancestors(target))
+            .filter_map(|node| self.observers.registrations.get(&node))
+            .flatten()
+            .filter(|registration| registration.options.subtree)
+            .map(|registration| (registration.observer, registration.options.clone()))
+            .collect();
+        for &node in &removed_nodes {
+            for (observer, options) in &transient {
+                let registration = Registration { observer: *observer, options: options.clone(), transient: true };
+                self.observers.registrations.entry(node).or_default().push(registration);
+            }
+        }
+        self.queue_record(MutationRecord {
+            added_nodes,
+            removed_nodes,
+            previous_sibling,
+            next_sibling,
+            ..self.empty_record(MutationKind::ChildList, target)
+        });
+    }
+
+    pub(super) fn queue_attribute(&mut self, target: NodeId, name: &str, old_value: Option<String>) {
+        self.queue_record(MutationRecord {
+            attribute_name: Some(name.to_string()),
+            old_value,
+            ..self.empty_record(MutationKind::Attributes, target)
+        });
+    }
+
+    pub(super) fn queue_character_data(&mut self, target: NodeId, old_value: String) {
+        self.queue_record(MutationRecord { old_value: Some(old_value), ..self.empty_record(MutationKind::CharacterData, target) });
+    }
+
+    fn empty_record(&self, kind: MutationKind, target: NodeId) -> MutationRecord {
+        MutationRecord {
+            kind,
+            target,
+            added_nodes: Vec::new(),
+            removed_nodes: Vec::new(),
+            previous_sibling: None,
+            next_sibling: None,
+            attribute_name: None,
+            old_value: None,
+        }
+    }
+
+    // give the record to every observer of the target or of an ancestor
+    // observing its subtree that asked for it, once each
+    fn queue_record(&mut self, record: MutationRecord) {
+        if self.observers.registrations.is_empty() {
+            return;
+        }
+        // the observers with whether they want the old value
+        let mut interested: BTreeMap<ObserverId, bool> = BTreeMap::new();
+        for node in std::iter::once(record.target).chain(self.ancestors(record.target)) {
+            let registrations = match self.observers.registrations.get(&node) {
+                Some(registrations) => registrations,
+                None => continue,
+            };
+            for Registration { observer, options, .. } in registrations {
+                if node != record.target && !options.subtree {
+                    continue;
+                }
+                let (wanted, old_value) = match record.kind {
+                    MutationKind::ChildList => (options.child_list, false),
+                    MutationKind::Attributes => {
+                        let name = record.attribute_name.as_ref();
+                        let filtered = options.attribute_filter.as_ref()
+                            .is_some_and(|filter| !name.is_some_and(|name| filter.contains(name)));
+                        (options.attributes && !filtered, options.attribute_old_value)
+                    }
+                    MutationKind::CharacterData => (options.character_data, options.character_data_old_value),
+                };
+                if wanted {
+                    *interested.entry(*observer).or_default() |= old_value;
+                }
+            }
+        }
+        for (observer, old_value) in interested {
+            let mut record = record.clone();
+            if !old_value {
+                record.old_value = None;
+            }
+            if let Some(observer) = self.observers.observers.get_mut(&observer) {
+                observer.records.push(record);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::NodeType;
+    use crate::dom::tests::parse;
+
+    fn observer(document: &mut Document) -> ObserverId {
+        document.create_mutation_observer(|_, _| {})
+    }
+
+    #[test]
+    fn test_child_list() {
+        let mut document = parse("<ul><li>a</li><li>b</li></ul>");
+        let ul = document.root();
+        let (a, b) = (document.first_child(ul).unwrap(), document.last_child(ul).unwrap());
+        let observer = observer(&mut document);
+        let options = MutationObserverInit { child_list: true, ..Default::default() };
+        document.observe(observer, ul, options).unwrap();
+
+        let c = document.create_node(NodeType::Text("c".to_owned()));
+        document.insert_before(ul, c, Some(b)).unwrap();
+        document.remove_child(ul, a).unwrap();
+        document.replace_child(ul, a, b).unwrap();
+        let records = document.take_records(observer);
+        assert_eq!(records.len(), 3);
+        assert_eq!((&records[0].added_nodes, records[0].previous_sibling, records[0].next_sibling), (&vec![c], Some(a), Some(b)));
+        assert_eq!((&records[1].removed_nodes, records[1].previous_sibling, records[1].next_sibling), (&vec![a], None, Some(c)));
+        assert_eq!((&records[2].added_nodes, &records[2].removed_nodes), (&vec![a], &vec![b]));
+        assert!(records.iter().all(|record| record.kind == MutationKind::ChildList && record.target == ul));
+        assert!(document.take_records(observer).is_empty());
+
+        // changes to the children aren't asked for without the subtree
+        document.set_text_content(a, "x");
+        document.set_attribute(ul, "class", "list");
+        assert!(document.take_records(observer).is_empty());
+
+        // nor anything once disconnected
+        document.disconnect(observer);
+        document.set_inner_html(ul, "<li>d</li>");
+        assert!(document.take_records(observer).is_empty());
+    }
+
+    #[test]
+    fn test_attributes_and_character_data() {
+        let mut document = parse("<div id=a title=t><p>text</p></div>");
+        let div = document.root();
+        let p = document.first_child(div).unwrap();
+        let text = document.first_child(p).unwrap();
+        let old_values = observer(&mut document);
+        document.observe(old_values, div, MutationObserverInit {
+            subtree: true,
+            attribute_old_value: true,
+            character_data_old_value: true,
+            attribute_filter: Some(vec!["id".to_owned(), "class".to_owned()]),
+            ..Default::default()
+        }).unwrap();
+        let others = observer(&mut document);
+        document.observe(others, p, MutationObserverInit { attributes: true, character_data: true, ..Default::default() }).unwrap();
+
+        document.set_attribute(div, "id", "b");
+        document.set_attribute(div, "title", "u");
+        document.set_attribute(p, "class", "new");
+        document.set_text_content(text, "changed");
+        document.remove_attribute(p, "class");
+
+        let records = document.take_records(old_values);
+        let summary: Vec<(MutationKind, NodeId, Option<&str>, Option<&str>)> = records.iter()
+            .map(|record| (record.kind, record.target, record.attribute_name.as_deref(), record.old_value.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            (MutationKind::Attributes, div, Some("id"), Some("a")),
+            (MutationKind::Attributes, p, Some("class"), None),
+            (MutationKind::CharacterData, text, None, Some("text")),
+            (MutationKind::Attributes, p, Some("class"), Some("new")),
+        ]);
+        // the text isn't under observation without the subtree, and no old values
+        let records = document.take_records(others);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.target == p && record.old_value.is_none()));
+
+        assert_eq!(document.observe(others, p, MutationObserverInit::default()), Err(DomError::Type));
+    }
+
+    #[test]
+    fn test_removed_nodes() {
+        let mut document = parse("<ul><li>a</li><li>b</li></ul>");
+        let ul = document.root();
+        let li = document.first_child(ul).unwrap();
+        let text = document.first_child(li).unwrap();
+        let targets = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&targets);
+        let observer = document.create_mutation_observer(move |_, records| {
+            seen.borrow_mut().extend(records.iter().map(|record| (record.kind, record.target)));
+        });
+        let options = MutationObserverInit { child_list: true, character_data: true, subtree: true, ..Default::default() };
+        document.observe(observer, ul, options).unwrap();
+
+        // a node removed from the subtree is still heard of until the
+        // observer is notified
+        document.detach(li);
+        document.set_text_content(text, "x");
+        document.notify_mutation_observers();
+        assert_eq!(*targets.borrow(), vec![(MutationKind::ChildList, ul), (MutationKind::CharacterData, text)]);
+
+        document.set_text_content(text, "y");
+        assert!(document.take_records(observer).is_empty());
+        // the subtree itself is still observed
+        let b = document.first_child(document.first_child(ul).unwrap()).unwrap();
+        document.set_text_content(b, "z");
+        assert_eq!(document.take_records(observer).len(), 1);
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut document = parse("<div><p>a</p></div>");
+        let div = document.root();
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&batches);
+        let observer = document.create_mutation_observer(move |document, records| {
+            // the callback's own changes come in the next batch
+            if seen.borrow().is_empty() {
+                let div = records[0].target;
+                document.set_attribute(div, "class", "seen");
+            }
+            seen.borrow_mut().push(records.len());
+        });
+        let options = MutationObserverInit { child_list: true, attributes: true, subtree: true, ..Default::default() };
+        document.observe(observer, div, options).unwrap();
+
+        document.set_inner_html(div, "<p>b</p><p>c</p>");
+        document.set_text_content(div, "");
+        document.notify_mutation_observers();
+        assert_eq!(*batches.borrow(), vec![2, 1]);
+        document.notify_mutation_observers();
+        assert_eq!(batches.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_notify_from_a_callback() {
+        let mut document = parse("<div><p>a</p></div>");
+        let div = document.root();
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&batches);
+        let observer = document.create_mutation_observer(move |document, records| {
+            seen.borrow_mut().push(records.iter().map(|record| record.kind).collect::<Vec<MutationKind>>());
+            if seen.borrow().len() == 1 {
+                let div = records[0].target;
+                document.set_attribute(div, "class", "seen");
+                // the record of that change waits for this callback to return
+                document.notify_mutation_observers();
+                assert_eq!(seen.borrow().len(), 1);
+            }
+        });
+        let others = Rc::new(RefCell::new(0));
+        let count = Rc::clone(&others);
+        let other = document.create_mutation_observer(move |_, records| *count.borrow_mut() += records.len());
+        let options = MutationObserverInit { child_list: true, attributes: true, ..Default::default() };
+        document.observe(observer, div, options.clone()).unwrap();
+        document.observe(other, div, options).unwrap();
+
+        document.set_text_content(div, "b");
+        document.notify_mutation_observers();
+        assert_eq!(*batches.borrow(), vec![vec![MutationKind::ChildList], vec![MutationKind::Attributes]]);
+        // the other observer got both records, from the inner call
+        assert_eq!(*others.borrow(), 2);
+        assert!(document.take_records(observer).is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+// Finding elements with css selectors, like querySelector() and closest()
+
+use super::{Document, DomError, DomResult, Node, NodeId, NodeType};
+use crate::css;
+
+fn parse(selectors: &str) -> DomResult<Vec<css::Selector>> {
+    css::parse_selectors(selectors).ok_or(DomError::Syntax)
+}
+
+fn selects(selectors: &[css::Selector], node_type: &NodeType) -> bool {
+    match node_type {
+        NodeType::Element(elem) => selectors.iter().any(|selector| selector.matches(elem)),
+        _ => false,
+    }
+}
+
+impl Node {
+    /// The first element under this node that any of `selectors` selects,
+    /// in document order. `selectors` is a list like `p.note, #main`.
+    pub fn query_selector(&self, selectors: &str) -> DomResult<Option<&Node>> {
+        let selectors = parse(selectors)?;
+        Ok(self.descendants().find(|node| selects(&selectors, &node.node_type)))
+    }
+
+    /// All the elements under this node that any of `selectors` selects,
+    /// in document order.
+    pub fn query_selector_all(&self, selectors: &str) -> DomResult<Vec<&Node>> {
+        let selectors = parse(selectors)?;
+        Ok(self.descendants().filter(|node| selects(&selectors, &node.node_type)).collect())
+    }
+
+    /// Whether this is an element that any of `selectors` selects.
+    pub fn matches(&self, selectors: &str) -> DomResult<bool> {
+        Ok(selects(&parse(selectors)?, &self.node_type))
+    }
+
+    /// Like `Document::closest`. An owned node doesn't know its parent, so
+    /// this node is the top of the search and `path` gives the indexes of
+    /// the children down from it to the node to start from. Gives
+    /// `DomError::NotFound` when there's no node at `path`.
+    pub fn closest(&self, path: &[usize], selectors: &str) -> DomResult<Option<&Node>> {
+        let selectors = parse(selectors)?;
+        let mut nodes = vec![self];
+        for &index in path {
+            let node = nodes.last().unwrap().children.get(index).ok_or(DomError::NotFound)?;
+            nodes.push(node);
+        }
+        Ok(nodes.into_iter().rev().find(|node| selects(&selectors, &node.node_type)))
+    }
+}
+
+impl Document {
+    /// The first element under `id` that any of `selectors` selects, in
+    /// document order.
+    pub fn query_selector(&self, id: NodeId, selectors: &str) -> DomResult<Option<NodeId>> {
+        let selectors = parse(selectors)?;
+        Ok(self.descendants(id).find(|&node| selects(&selectors, self.node_type(node))))
+    }
+
+    /// All the elements under `id` that any of `selectors` selects, in
+    /// document order.
+    pub fn query_selector_all(&self, id: NodeId, selectors: &str) -> DomResult<Vec<NodeId>> {
+        let selectors = parse(selectors)?;
+        Ok(self.descendants(id).filter(|&node| selects(&selectors, self.node_type(node))).collect())
+    }
+
+    /// The elements under `id` named `tag_name`, in document order. Names of
+    /// html elements match in any case and `*` matches every element.
+    pub fn get_elements_by_tag_name(&self, id: NodeId, tag_name: &str) -> Vec<NodeId> {
+        self.elements(id)
+            .filter(|&node| {
+                let elem = self.element(node).unwrap();
+                tag_name == "*" || elem.tag_name == tag_name || elem.is_html_element(tag_name)
+            })
+            .collect()
+    }
+
+    /// The elements under `id` that have all of the white space separated
+    /// `class_names`, in document order. Empty names match nothing.
+    pub fn get_elements_by_class_name(&self, id: NodeId, class_names: &str) -> Vec<NodeId> {
+        let class_names: Vec<&str> = class_names.split_ascii_whitespace().collect();
+        if class_names.is_empty() {
+            return Vec::new();
+        }
+        self.elements(id)
+            .filter(|&node| {
+                let classes = self.element(node).unwrap().classes();
+                class_names.iter().all(|name| classes.contains(name))
+            })
+            .collect()
+    }
+
+    /// Whether `id` is an element that any of `selectors` selects.
+    pub fn matches(&self, id: NodeId, selectors: &str) -> DomResult<bool> {
+        Ok(selects(&parse(selectors)?, self.node_type(id)))
+    }
+
+    /// The nearest of `id` and its ancestors that any of `selectors` selects.
+    pub fn closest(&self, id: NodeId, selectors: &str) -> DomResult<Option<NodeId>> {
+        let selectors = parse(selectors)?;
+        Ok(std::iter::once(id).chain(self.ancestors(id))
+            .find(|&node| selects(&selectors, self.node_type(node))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    const SOURCE: &str = "<div id=main><p class='note first'>a<b class=note>b</b></p><p>c</p><ul><li class=note>d</li></ul></div>";
+
+    fn tag(node: &Node) -> String {
+        match &node.node_type {
+            NodeType::Element(elem) => elem.tag_name.clone(),
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn test_node_queries() {
+        let div = Parser::new(SOURCE).parse().unwrap();
+        let notes: Vec<String> = div.query_selector_all(".note").unwrap().into_iter().map(tag).collect();
+        assert_eq!(notes, vec!["p", "b", "li"]);
+        let first = div.query_selector("li, b").unwrap().unwrap();
+        assert_eq!(tag(first), "b");
+        assert_eq!(div.query_selector_all("p.note.first, ul").unwrap().len(), 2);
+        assert!(div.query_selector("#main").unwrap().is_none(), "the node itself isn't searched");
+        assert!(div.matches("div#main").unwrap() && !div.matches("p").unwrap());
+        assert_eq!(div.query_selector_all("*").unwrap().len(), 5);
+        // html names match in any case, foreign ones don't
+        let mixed = Parser::new("<div><P>a</P><svg><foreignObject/></svg></div>").parse().unwrap();
+        assert_eq!(mixed.query_selector("p").unwrap().map(tag), Some("P".to_owned()));
+        assert_eq!(mixed.query_selector_all("foreignObject").unwrap().len(), 1);
+        assert!(mixed.query_selector("FOREIGNOBJECT").unwrap().is_none());
+
+        // from the text in <b> up
+        assert_eq!(div.closest(&[0, 1, 0], ".note").unwrap().map(tag), Some("b".to_owned()));
+        assert_eq!(div.closest(&[0, 1], "p").unwrap().map(tag), Some("p".to_owned()));
+        assert_eq!(div.closest(&[0, 1], "#main").unwrap().map(tag), Some("div".to_owned()));
+        assert!(div.closest(&[0, 1], "ul").unwrap().is_none());
+        assert_eq!(div.closest(&[0, 5], "p").err(), Some(DomError::NotFound));
+
+        assert_eq!(div.query_selector("p..note").err(), Some(DomError::Syntax));
+        assert_eq!(div.query_selector_all("p,").err(), Some(DomError::Syntax));
+        assert_eq!(div.matches("").err(), Some(DomError::Syntax));
+    }
+
+    #[test]
+    fn test_document_queries() {
+        let document = Parser::new(SOURCE).tolerant().parse_tree().unwrap();
+        let root = document.root();
+        let notes = document.query_selector_all(root, ".note").unwrap();
+        let names: Vec<&str> = notes.iter().map(|&id| document.element(id).unwrap().tag_name.as_str()).collect();
+        assert_eq!(names, vec!["p", "b", "li"]);
+
+        let b = notes[1];
+        assert_eq!(document.closest(b, ".note").unwrap(), Some(b));
+        assert_eq!(document.closest(b, "p").unwrap(), Some(notes[0]));
+        let main = document.closest(b, "#main").unwrap().unwrap();
+        assert_eq!(document.closest(b, "ul").unwrap(), None);
+        assert!(document.matches(main, "div").unwrap());
+        assert!(!document.matches(document.first_child(b).unwrap(), "*").unwrap());
+
+        // searching below a node stays below it
+        let ul = document.query_selector(main, "ul").unwrap().unwrap();
+        assert_eq!(document.query_selector_all(notes[0], "li, p").unwrap(), vec![]);
+        assert_eq!(document.query_selector_all(ul, "li").unwrap(), vec![notes[2]]);
+        assert_eq!(document.closest(b, "#").err(), Some(DomError::Syntax));
+    }
+
+    #[test]
+    fn test_lookups() {
+        let source = "<div id=a class='x  y'><P class=y>1</P><svg><circle class='x y'/><foreignObject id=b /></svg><p id=a class=\"y\tx\">2</p></div>";
+        let document = Document::from_node(Parser::new(source).parse().unwrap());
+        let div = document.root();
+        let names = |ids: Vec<NodeId>| ids.into_iter().map(|id| document.element(id).unwrap().tag_name.clone()).collect::<Vec<String>>();
+
+        assert_eq!(names(document.get_elements_by_tag_name(div, "p")), vec!["P", "p"]);
+        assert_eq!(names(document.get_elements_by_tag_name(div, "foreignObject")), vec!["foreignObject"]);
+        assert!(document.get_elements_by_tag_name(div, "FOREIGNOBJECT").is_empty());
+        assert_eq!(document.get_elements_by_tag_name(div, "*").len(), 5);
+        // selectors name elements the same way
+        for name in &["p", "P", "foreignObject", "FOREIGNOBJECT"] {
+            assert_eq!(document.query_selector_all(div, name).unwrap(), document.get_elements_by_tag_name(div, name));
+        }
+
+        assert_eq!(names(document.get_elements_by_class_name(div, " y  x ")), vec!["circle", "p"]);
+        assert_eq!(names(document.get_elements_by_class_name(div, "y")), vec!["P", "circle", "p"]);
+        assert!(document.get_elements_by_class_name(div, " ").is_empty());
+
+        // the first of two elements with the same id wins, starting with the root
+        assert_eq!(document.get_element_by_id("a"), Some(div));
+        assert_eq!(names(document.get_element_by_id("b").into_iter().collect()), vec!["foreignObject"]);
+        assert_eq!(document.get_element_by_id("c"), None);
+    }
+}
@@ -0,0 +1,638 @@
+// Walking a tree in the usual orders, and TreeWalker and NodeIterator
+// https://dom.spec.whatwg.org/#traversal
+
+use std::collections::VecDeque;
+
+use super::{Document, Node, NodeId, NodeType};
+
+// bits of `what_to_show`, one per node type, with the DOM's values
+pub const SHOW_ALL: u32 = 0xFFFF_FFFF;
+pub const SHOW_ELEMENT: u32 = 0x1;
+pub const SHOW_TEXT: u32 = 0x4;
+pub const SHOW_CDATA_SECTION: u32 = 0x8;
+pub const SHOW_PROCESSING_INSTRUCTION: u32 = 0x40;
+pub const SHOW_COMMENT: u32 = 0x80;
+pub const SHOW_DOCUMENT: u32 = 0x100;
+pub const SHOW_DOCUMENT_TYPE: u32 = 0x200;
+
+/// What the filter of a `TreeWalker` or `NodeIterator` says about a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    Accept,
+    // leave out the node and, for a TreeWalker, its descendants too
+    Reject,
+    // leave out the node but not its descendants
+    Skip,
+}
+
+fn show_bit(node_type: &NodeType) -> u32 {
+    match node_type {
+        NodeType::Element(_) => SHOW_ELEMENT,
+        NodeType::Text(_) => SHOW_TEXT,
+        NodeType::CData(_) => SHOW_CDATA_SECTION,
+        NodeType::ProcessingInstruction(..) => SHOW_PROCESSING_INSTRUCTION,
+        NodeType::Comment(_) => SHOW_COMMENT,
+        NodeType::Document(_) => SHOW_DOCUMENT,
+        NodeType::Doctype(_) => SHOW_DOCUMENT_TYPE,
+    }
+}
+
+impl Node {
+    /// This node and everything under it, each node before its children.
+    pub fn preorder(&self) -> impl Iterator<Item = &Node> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+
+    /// Everything under this node in document order, without the node itself.
+    pub fn descendants(&self) -> impl Iterator<Item = &Node> {
+        self.preorder().skip(1)
+    }
+
+    /// This node and everything under it, each node after its children.
+    pub fn postorder(&self) -> impl Iterator<Item = &Node> {
+        // nodes with whether their children were pushed already
+        let mut stack = vec![(self, false)];
+        std::iter::from_fn(move || loop {
+            let (node, expanded) = stack.pop()?;
+            if expanded || node.children.is_empty() {
+                return Some(node);
+            }
+            stack.push((node, true));
+            stack.extend(node.children.iter().rev().map(|child| (child, false)));
+        })
+    }
+
+    /// This node and everything under it, level by level.
+    pub fn breadth_first(&self) -> impl Iterator<Item = &Node> {
+        let mut queue = VecDeque::from(vec![self]);
+        std::iter::from_fn(move || {
+            let node = queue.pop_front()?;
+            queue.extend(node.children.iter());
+            Some(node)
+        })
+    }
+
+    /// The elements under this node in document order.
+    pub fn elements(&self) -> impl Iterator<Item = &Node> {
+        self.descendants().filter(|node| matches!(node.node_type, NodeType::Element(_)))
+    }
+}
+
+impl Document {
+    /// `id` and everything under it, each node before its children.
+    pub fn preorder(&self, id: NodeId) -> Preorder<'_> {
+        Preorder { document: self, root: id, next: Some(id) }
+    }
+
+    /// Everything under `id` in document order, without `id` itself.
+    pub fn descendants(&self, id: NodeId) -> Preorder<'_> {
+        Preorder { document: self, root: id, next: self.first_child(id) }
+    }
+
+    /// `id` and everything under it, each node after its children.
+    pub fn postorder(&self, id: NodeId) -> Postorder<'_> {
+        Postorder { document: self, root: id, next: Some(self.deepest_first_child(id)) }
+    }
+
+    /// `id` and everything under it, level by level.
+    pub fn breadth_first(&self, id: NodeId) -> BreadthFirst<'_> {
+        BreadthFirst { document: self, queue: VecDeque::from(vec![id]) }
+    }
+
+    /// The parent of `id`, its parent and so on up to the top of the tree.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors { document: self, next: self.parent(id) }
+    }
+
+    /// The elements under `id` in document order.
+    pub fn elements(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.descendants(id).filter(move |&node| self.element(node).is_some())
+    }
+
+    fn deepest_first_child(&self, mut id: NodeId) -> NodeId {
+        while let Some(child) = self.first_child(id) {
+            id = child;
+        }
+        id
+    }
+
+    fn deepest_last_child(&self, mut id: NodeId) -> NodeId {
+        while let Some(child) = self.last_child(id) {
+            id = child;
+        }
+        id
+    }
+
+    // the node after `id` in document order, staying under `root`
+    fn following(&self, root: NodeId, id: NodeId) -> Option<NodeId> {
+        if let Some(child) = self.first_child(id) {
+            return Some(child);
+        }
+        std::iter::successors(Some(id), |&node| self.parent(node))
+            .take_while(|&node| node != root)
+            .find_map(|node| self.next_sibling(node))
+    }
+
+    // the node before `id` in document order, staying under `root`
+    fn preceding(&self, root: NodeId, id: NodeId) -> Option<NodeId> {
+        if id == root {
+            return None;
+        }
+        match self.previous_sibling(id) {
+            Some(sibling) => Some(self.deepest_last_child(sibling)),
+            None => self.parent(id),
+        }
+    }
+}
+
+pub struct Preorder<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Preorder<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.following(self.root, id);
+        Some(id)
+    }
+}
+
+pub struct Postorder<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Postorder<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = if id == self.root {
+            None
+        } else {
+            match self.document.next_sibling(id) {
+                Some(sibling) => Some(self.document.deepest_first_child(sibling)),
+                None => self.document.parent(id),
+            }
+        };
+        Some(id)
+    }
+}
+
+pub struct BreadthFirst<'a> {
+    document: &'a Document,
+    queue: VecDeque<NodeId>,
+}
+
+impl Iterator for BreadthFirst<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.queue.pop_front()?;
+        self.queue.extend(self.document.children(id));
+        Some(id)
+    }
+}
+
+pub struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.parent(id);
+        Some(id)
+    }
+}
+
+type Filter<'a> = Box<dyn Fn(&Document, NodeId) -> FilterResult + 'a>;
+
+// what_to_show first, then the filter
+fn filter_node(document: &Document, what_to_show: u32, filter: &Option<Filter<'_>>, id: NodeId) -> FilterResult {
+    if what_to_show & show_bit(document.node_type(id)) == 0 {
+        return FilterResult::Skip;
+    }
+    match filter {
+        Some(filter) => filter(document, id),
+        None => FilterResult::Accept,
+    }
+}
+
+/// Moves around the tree under `root` in every direction, seeing only the
+/// nodes `what_to_show` and the filter accept.
+/// https://dom.spec.whatwg.org/#interface-treewalker
+pub struct TreeWalker<'a> {
+    document: &'a Document,
+    root: NodeId,
+    what_to_show: u32,
+    filter: Option<Filter<'a>>,
+    current_node: NodeId,
+}
+
+impl<'a> TreeWalker<'a> {
+    /// A walker starting at `root`, showing the kinds of nodes whose bits
+    /// are set in `what_to_show`, like `SHOW_ELEMENT | SHOW_TEXT`.
+    pub fn new(document: &'a Document, root: NodeId, what_to_show: u32) -> TreeWalker<'a> {
+        TreeWalker { document, root, what_to_show, filter: None, current_node: root }
+    }
+
+    /// Ask `filter` about every node `what_to_show` lets through.
+    pub fn with_filter<F: Fn(&Document, NodeId) -> FilterResult + 'a>(mut self, filter: F) -> TreeWalker<'a> {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn current_node(&self) -> NodeId {
+        self.current_node
+    }
+
+    pub fn set_current_node(&mut self, id: NodeId) {
+        self.current_node = id;
+    }
+
+    fn filter(&self, id: NodeId) -> FilterResult {
+        filter_node(self.document, self.what_to_show, &self.filter, id)
+    }
+
+    fn accept(&mut self, id: NodeId) -> Option<NodeId> {
+        self.current_node = id;
+        Some(id)
+    }
+
+    pub fn parent_node(&mut self) -> Option<NodeId> {
+        let mut node = self.current_node;
+        while node != self.root {
+            node = self.document.parent(node)?;
+            if self.filter(node) == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+        None
+    }
+
+    pub fn first_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(true)
+    }
+
+    pub fn last_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(false)
+    }
+
+    pub fn previous_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(false)
+    }
+
+    pub fn next_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(true)
+    }
+
+    // https://dom.spec.whatwg.org/#concept-traverse-children
+    fn traverse_children(&mut self, first: bool) -> Option<NodeId> {
+        let document = self.document;
+        let child = |id| if first { document.first_child(id) } else { document.last_child(id) };
+        let sibling = |id| if first { document.next_sibling(id) } else { document.previous_sibling(id) };
+
+        let mut node = child(self.current_node)?;
+        loop {
+            match self.filter(node) {
+                FilterResult::Accept => return self.accept(node),
+                FilterResult::Skip => {
+                    if let Some(grandchild) = child(node) {
+                        node = grandchild;
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+            loop {
+                if let Some(next) = sibling(node) {
+                    node = next;
+                    break;
+                }
+                let parent = document.parent(node)?;
+                if parent == self.root || parent == self.current_node {
+                    return None;
+                }
+                node = parent;
+            }
+        }
+    }
+
+    // https://dom.spec.whatwg.org/#concept-traverse-siblings
+    fn traverse_siblings(&mut self, next: bool) -> Option<NodeId> {
+        let document = self.document;
+        let sibling = |id| if next { document.next_sibling(id) } else { document.previous_sibling(id) };
+        let child = |id| if next { document.first_child(id) } else { document.last_child(id) };
+
+        let mut node = self.current_node;
+        if node == self.root {
+            return None;
+        }
+        loop {
+            let mut candidate = sibling(node);
+            while let Some(current) = candidate {
+                node = current;
+                let result = self.filter(node);
+                if result == FilterResult::Accept {
+                    return self.accept(node);
+                }
+                candidate = child(node);
+                if result == FilterResult::Reject || candidate.is_none() {
+                    candidate = sibling(node);
+                }
+            }
+            node = document.parent(node)?;
+            if node == self.root || self.filter(node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+
+    /// The node before the current one in document order.
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        let document = self.document;
+        let mut node = self.current_node;
+        while node != self.root {
+            while let Some(sibling) = document.previous_sibling(node) {
+                node = sibling;
+                let mut result = self.filter(node);
+                while result != FilterResult::Reject {
+                    match document.last_child(node) {
+                        Some(child) => {
+                            node = child;
+                            result = self.filter(node);
+                        }
+                        None => break,
+                    }
+                }
+                if result == FilterResult::Accept {
+                    return self.accept(node);
+                }
+            }
+            node = document.parent(node)?;
+            if self.filter(node) == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+        None
+    }
+
+    /// The node after the current one in document order.
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        let document = self.document;
+        let mut node = self.current_node;
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                match document.first_child(node) {
+                    Some(child) => {
+                        node = child;
+                        result = self.filter(node);
+                        if result == FilterResult::Accept {
+                            return self.accept(node);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            // the next sibling of the nearest ancestor that has one, under root
+            node = std::iter::successors(Some(node), |&ancestor| document.parent(ancestor))
+                .take_while(|&ancestor| ancestor != self.root)
+                .find_map(|ancestor| document.next_sibling(ancestor))?;
+            result = self.filter(node);
+            if result == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+    }
+}
+
+/// Goes forward and back through the nodes under `root` in document order,
+/// seeing only the nodes `what_to_show` and the filter accept. Rejecting a
+/// node doesn't leave out its descendants, unlike with a `TreeWalker`.
+/// https://dom.spec.whatwg.org/#interface-nodeiterator
+pub struct NodeIterator<'a> {
+    document: &'a Document,
+    root: NodeId,
+    what_to_show: u32,
+    filter: Option<Filter<'a>>,
+    reference_node: NodeId,
+    pointer_before_reference: bool,
+}
+
+impl<'a> NodeIterator<'a> {
+    pub fn new(document: &'a Document, root: NodeId, what_to_show: u32) -> NodeIterator<'a> {
+        NodeIterator {
+            document,
+            root,
+            what_to_show,
+            filter: None,
+            reference_node: root,
+            pointer_before_reference: true,
+        }
+    }
+
+    /// Ask `filter` about every node `what_to_show` lets through.
+    pub fn with_filter<F: Fn(&Document, NodeId) -> FilterResult + 'a>(mut self, filter: F) -> NodeIterator<'a> {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// The node last returned, or the root before any was.
+    pub fn reference_node(&self) -> NodeId {
+        self.reference_node
+    }
+
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        self.traverse(true)
+    }
+
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        self.traverse(false)
+    }
+
+    // https://dom.spec.whatwg.org/#concept-nodeiterator-traverse
+    fn traverse(&mut self, next: bool) -> Option<NodeId> {
+        let mut node = self.reference_node;
+        let mut before = self.pointer_before_reference;
+        loop {
+            if next {
+                if before {
+                    before = false;
+                } else {
+                    node = self.document.following(self.root, node)?;
+                }
+            } else if before {
+                node = self.document.preceding(self.root, node)?;
+            } else {
+                before = true;
+            }
+            if filter_node(self.document, self.what_to_show, &self.filter, node) == FilterResult::Accept {
+                break;
+            }
+        }
+        self.reference_node = node;
+        self.pointer_before_reference = before;
+        Some(node)
+    }
+}
+
+impl Iterator for NodeIterator<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        self.next_node()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    // <div><p>a<b>b</b></p><!--c--><ul><li>d</li></ul></div>
+    const SOURCE: &str = "<div><p>a<b>b</b></p><!--c--><ul><li>d</li></ul></div>";
+
+    fn name(node_type: &NodeType) -> String {
+        match node_type {
+            NodeType::Element(elem) => elem.tag_name.clone(),
+            NodeType::Text(text) => text.clone(),
+            NodeType::Comment(_) => "#comment".to_string(),
+            _ => "#other".to_string(),
+        }
+    }
+
+    fn names(document: &Document, ids: impl Iterator<Item = NodeId>) -> Vec<String> {
+        ids.map(|id| name(document.node_type(id))).collect()
+    }
+
+    #[test]
+    fn test_node_orders() {
+        let div = Parser::new(SOURCE).parse().unwrap();
+        let names = |nodes: Vec<&Node>| nodes.into_iter().map(|node| name(&node.node_type)).collect::<Vec<String>>();
+        assert_eq!(names(div.preorder().collect()), vec!["div", "p", "a", "b", "b", "#comment", "ul", "li", "d"]);
+        assert_eq!(names(div.descendants().collect()).len(), 8);
+        assert_eq!(names(div.postorder().collect()), vec!["a", "b", "b", "p", "#comment", "d", "li", "ul", "div"]);
+        assert_eq!(names(div.breadth_first().collect()), vec!["div", "p", "#comment", "ul", "a", "b", "li", "b", "d"]);
+        assert_eq!(names(div.elements().collect()), vec!["p", "b", "ul", "li"]);
+    }
+
+    #[test]
+    fn test_document_orders() {
+        let document = Document::from_node(Parser::new(SOURCE).parse().unwrap());
+        let div = document.root();
+        assert_eq!(names(&document, document.preorder(div)), vec!["div", "p", "a", "b", "b", "#comment", "ul", "li", "d"]);
+        assert_eq!(names(&document, document.postorder(div)), vec!["a", "b", "b", "p", "#comment", "d", "li", "ul", "div"]);
+        assert_eq!(names(&document, document.breadth_first(div)), vec!["div", "p", "#comment", "ul", "a", "b", "li", "b", "d"]);
+        assert_eq!(names(&document, document.elements(div)), vec!["p", "b", "ul", "li"]);
+
+        // a subtree is walked on its own
+        let p = document.first_child(div).unwrap();
+        assert_eq!(names(&document, document.descendants(p)), vec!["a", "b", "b"]);
+        assert_eq!(names(&document, document.postorder(p)), vec!["a", "b", "b", "p"]);
+        let b = document.last_child(p).unwrap();
+        let text = document.first_child(b).unwrap();
+        assert_eq!(names(&document, document.ancestors(text)), vec!["b", "p", "div"]);
+        assert_eq!(document.ancestors(div).count(), 0);
+        assert_eq!(names(&document, document.postorder(text)), vec!["b"]);
+    }
+
+    #[test]
+    fn test_tree_walker() {
+        let document = Document::from_node(Parser::new(SOURCE).parse().unwrap());
+        let div = document.root();
+
+        let mut walker = TreeWalker::new(&document, div, SHOW_ELEMENT);
+        let mut forward = Vec::new();
+        while let Some(id) = walker.next_node() {
+            forward.push(id);
+        }
+        assert_eq!(names(&document, forward.iter().copied()), vec!["p", "b", "ul", "li"]);
+        let mut backward = Vec::new();
+        while let Some(id) = walker.previous_node() {
+            backward.push(id);
+        }
+        assert_eq!(names(&document, backward.into_iter()), vec!["ul", "b", "p", "div"]);
+        assert_eq!(walker.current_node(), div);
+
+        // skipping <p> reaches into it, rejecting it leaves out <b> too
+        let skip_p = TreeWalker::new(&document, div, SHOW_ELEMENT | SHOW_COMMENT)
+            .with_filter(|document, id| match document.element(id) {
+                Some(elem) if elem.tag_name == "p" => FilterResult::Skip,
+                _ => FilterResult::Accept,
+            });
+        let mut walker = skip_p;
+        assert_eq!(walker.first_child().map(|id| name(document.node_type(id))), Some("b".to_string()));
+        assert_eq!(walker.next_sibling().map(|id| name(document.node_type(id))), Some("#comment".to_string()));
+        assert_eq!(walker.next_sibling().map(|id| name(document.node_type(id))), Some("ul".to_string()));
+        assert_eq!(walker.next_sibling(), None);
+        assert_eq!(walker.last_child().map(|id| name(document.node_type(id))), Some("li".to_string()));
+        assert_eq!(walker.parent_node().map(|id| name(document.node_type(id))), Some("ul".to_string()));
+        assert_eq!(walker.parent_node(), Some(div));
+        assert_eq!(walker.parent_node(), None);
+
+        let mut walker = TreeWalker::new(&document, div, SHOW_ALL)
+            .with_filter(|document, id| match document.element(id) {
+                Some(elem) if elem.tag_name == "p" => FilterResult::Reject,
+                _ => FilterResult::Accept,
+            });
+        assert_eq!(walker.first_child().map(|id| name(document.node_type(id))), Some("#comment".to_string()));
+        assert_eq!(walker.previous_sibling(), None);
+        walker.set_current_node(div);
+        let mut all = Vec::new();
+        while let Some(id) = walker.next_node() {
+            all.push(id);
+        }
+        assert_eq!(names(&document, all.into_iter()), vec!["#comment", "ul", "li", "d"]);
+    }
+
+    #[test]
+    fn test_node_iterator() {
+        let document = Document::from_node(Parser::new(SOURCE).parse().unwrap());
+        let div = document.root();
+        let ids: Vec<NodeId> = NodeIterator::new(&document, div, SHOW_TEXT).collect();
+        assert_eq!(names(&document, ids.into_iter()), vec!["a", "b", "d"]);
+
+        // rejecting a node still shows its descendants
+        let mut iterator = NodeIterator::new(&document, div, SHOW_ELEMENT | SHOW_TEXT)
+            .with_filter(|document, id| match document.element(id) {
+                Some(elem) if elem.tag_name == "p" || elem.tag_name == "div" => FilterResult::Reject,
+                _ => FilterResult::Accept,
+            });
+        let forward: Vec<NodeId> = iterator.by_ref().collect();
+        assert_eq!(names(&document, forward.into_iter()), vec!["a", "b", "b", "ul", "li", "d"]);
+        assert_eq!(name(document.node_type(iterator.reference_node())), "d");
+        // going back starts from the node last returned
+        assert_eq!(iterator.previous_node().map(|id| name(document.node_type(id))), Some("d".to_string()));
+        assert_eq!(iterator.previous_node().map(|id| name(document.node_type(id))), Some("li".to_string()));
+        assert_eq!(iterator.next_node().map(|id| name(document.node_type(id))), Some("li".to_string()));
+        let mut backward = Vec::new();
+        while let Some(id) = iterator.previous_node() {
+            backward.push(id);
+        }
+        assert_eq!(names(&document, backward.into_iter()), vec!["li", "ul", "b", "b", "a"]);
+        assert_eq!(iterator.root(), div);
+    }
+}
@@ -1,192 +1,777 @@
 #![allow(unused)]
 
+mod encoding;
+mod entities;
+mod entity_table;
+mod foreign;
+mod quirks;
+mod serialize;
+mod tokenizer;
+
+pub use encoding::{decode, DecodedInput, EncodingSource};
+pub(crate) use encoding::meta_encoding;
+pub use serialize::Serializer;
+pub use tokenizer::{Attribute, ReaderTokens, Tag, Token, Tokenizer};
+
 use crate::dom;
+use std::collections::HashMap;
 use std::collections::HashSet;
+use indexmap::map::Entry;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// An error met while parsing, with the position where it happened.
+/// `line` and `column` are 1-based, `column` counts chars not bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+// elements that never have content nor an end tag, with or without `/>`
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+// elements whose content is plain text up to their end tag
+const RAW_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "script", "style", "xmp"];
+// like RAW_TEXT_ELEMENTS, but character references are decoded
+const RCDATA_ELEMENTS: &[&str] = &["textarea", "title"];
+// elements whose start tag closes an open <p>
+const CLOSES_P: &[&str] = &[
+    "address", "article", "aside", "blockquote", "center", "dd", "details", "dialog", "dir",
+    "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3",
+    "h4", "h5", "h6", "header", "hgroup", "hr", "li", "listing", "main", "menu", "nav", "ol",
+    "p", "pre", "section", "summary", "table", "ul",
+];
+// elements that stop the search for an open element to close implicitly
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "applet", "button", "caption", "html", "marquee", "object", "table", "td", "template", "th",
+];
+// elements whose end tag may be omitted without it being an error
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "body", "caption", "colgroup", "dd", "dt", "head", "html", "li", "optgroup", "option", "p",
+    "rb", "rp", "rt", "rtc", "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+// elements that belong in <head> when they show up before any body content
+const HEAD_CONTENT: &[&str] = &[
+    "base", "basefont", "bgsound", "link", "meta", "noscript", "script", "style", "template",
+    "title",
+];
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+// elements that drop a newline right after their start tag, so that
+// `<pre>\ncode</pre>` holds just `code`
+const SKIPS_LEADING_NEWLINE: &[&str] = &["listing", "pre", "textarea"];
 
 pub struct Parser {
-    pos: usize,
-    input: String,
-    
-    open_tags: HashSet<String>
+    tokenizer: Tokenizer,
+    // the next token and where it was found, once looked at
+    peeked: Option<(Token, dom::SourceRange)>,
+    // where the last token consumed ends
+    last_end: dom::SourcePosition,
+    // how many of the tokenizer's errors have been taken over
+    tokenizer_errors: usize,
+
+    void_elements: HashSet<String>,
+
+    // recover from malformed markup the way HTML5 browsers do
+    tolerant: bool,
+    // names of the elements currently being parsed, outermost first
+    open_elements: Vec<String>,
+    // the namespace of each of open_elements
+    open_namespaces: Vec<dom::Namespace>,
+    // how many of the open elements stand for the context of a fragment,
+    // which nothing in the fragment can close
+    context_elements: usize,
+    // problems recovered from in tolerant mode
+    errors: Vec<ParseError>,
+    // set by a malformed doctype, which always means quirks mode
+    force_quirks: bool,
 }
 
 impl Parser {
     pub fn new<S>(source: S) -> Parser where S: ToString {
+        let mut tokenizer = Tokenizer::new(source);
+        tokenizer.set_preserve_case(true);
         Parser {
-            pos: 0,
-            input: source.to_string(),
-            open_tags: ["meta", "br", "link"].iter().map(|item| item.to_string()).collect()
+            last_end: tokenizer.position(),
+            tokenizer,
+            peeked: None,
+            tokenizer_errors: 0,
+            void_elements: VOID_ELEMENTS.iter().map(|item| item.to_string()).collect(),
+            tolerant: false,
+            open_elements: Vec::new(),
+            open_namespaces: Vec::new(),
+            context_elements: 0,
+            errors: Vec::new(),
+            force_quirks: false,
         }
     }
 
-    pub fn parse(&mut self) -> dom::Node {
-        let mut nodes = self.parse_nodes();
+    /// Switch to HTML5-style tree construction: missing end tags are implied,
+    /// stray end tags are dropped, and the result is always an `html` element
+    /// with a `head` and a `body`. The problems recovered from are kept in
+    /// `errors()` instead of failing the parse.
+    pub fn tolerant(mut self) -> Parser {
+        self.tolerant = true;
+        // tag and attribute names are case-insensitive
+        self.tokenizer.set_preserve_case(false);
+        self
+    }
 
-        if nodes.len() == 1 {
-            nodes.swap_remove(0)
+    /// Problems the parser recovered from, in source order. Strict mode fails
+    /// on most of them, but still records duplicate attributes here.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Parse the root element. The doctype and the comments around the root
+    /// are dropped, see `parse_document` to keep them.
+    pub fn parse(&mut self) -> ParseResult<dom::Node> {
+        let (_, root, _) = self.parse_top_level()?;
+        Ok(root)
+    }
+
+    /// Parse a whole document into a `Document` node holding the doctype,
+    /// the comments around the root element and the root element itself,
+    /// along with the quirks mode the doctype asks for.
+    pub fn parse_document(&mut self) -> ParseResult<dom::Node> {
+        let (prolog, root, epilog) = self.parse_top_level()?;
+        let doctype = prolog.iter().find_map(|node| match &node.node_type {
+            dom::NodeType::Doctype(doctype) => Some(doctype),
+            _ => None,
+        });
+        let quirks_mode = if self.force_quirks {
+            dom::QuirksMode::Quirks
         } else {
-            dom::elem("html".to_string(), dom::AttrMap::new(), nodes)
+            quirks::quirks_mode(doctype)
+        };
+
+        let mut children = prolog;
+        children.push(root);
+        children.extend(epilog);
+        let mut document = dom::document(quirks_mode, children);
+        let start = dom::SourcePosition { offset: 0, line: 1, column: 1 };
+        document.source = Some(dom::SourceRange { start, end: self.tokenizer.position() });
+        Ok(document)
+    }
+
+    /// Parse the whole document like `parse_document`, into a `dom::Document`
+    /// whose nodes link to their parent and siblings.
+    pub fn parse_tree(&mut self) -> ParseResult<dom::Document> {
+        Ok(dom::Document::from_node(self.parse_document()?))
+    }
+
+    /// Parse the input as the content of `context`, the way `innerHTML` is
+    /// parsed: `<li>` items for a `<ul>`, text for a `<textarea>`, table rows
+    /// with their implied `<tbody>` for a `<table>`. Nothing is wrapped
+    /// in `html`, `head` or `body`.
+    pub fn parse_fragment(&mut self, context: &dom::ElementData) -> ParseResult<Vec<dom::Node>> {
+        let name = if self.tolerant { context.tag_name.to_ascii_lowercase() } else { context.tag_name.clone() };
+        if let Some(decode) = self.text_only_content(&name).filter(|_| !context.is_foreign()) {
+            self.tokenizer.set_text_only(decode);
         }
+        self.open_elements.push(name);
+        self.open_namespaces.push(context.namespace);
+        self.context_elements = self.open_elements.len();
+
+        let nodes = self.parse_nodes()?;
+        if !self.tolerant && self.peek()?.is_some() {
+            return self.error_at(self.peek_source().start, "unexpected close tag at top level");
+        }
+        Ok(nodes)
     }
 
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    // split the top level into the doctype and comments before the root,
+    // the root, and the comments after it. White space around the root is dropped
+    fn parse_top_level(&mut self) -> ParseResult<(Vec<dom::Node>, dom::Node, Vec<dom::Node>)> {
+        fn is_misc(node: &dom::Node) -> bool {
+            matches!(node.node_type, dom::NodeType::Doctype(_)
+                | dom::NodeType::Comment(_)
+                | dom::NodeType::ProcessingInstruction(..)) || is_whitespace_text(node)
+        }
+
+        let mut nodes = self.parse_nodes()?;
+        if !self.tolerant && self.peek()?.is_some() {
+            return self.error_at(self.peek_source().start, "unexpected close tag at top level");
+        }
+
+        let lead = nodes.iter().position(|node| !is_misc(node)).unwrap_or(nodes.len());
+        let trail = nodes.iter().rposition(|node| !is_misc(node)).map_or(lead, |i| i + 1);
+        let mut epilog = nodes.split_off(trail);
+        let mut content = nodes.split_off(lead);
+        let mut prolog = nodes;
+        prolog.retain(|node| !is_whitespace_text(node));
+        epilog.retain(|node| !is_whitespace_text(node));
+
+        let root = if self.tolerant {
+            build_document(content)
+        } else if content.len() == 1 {
+            content.swap_remove(0)
+        } else {
+            dom::elem("html".to_string(), dom::AttrMap::new(), content)
+        };
+        Ok((prolog, root, epilog))
+    }
+
+    fn error_at<T>(&self, position: dom::SourcePosition, message: &str) -> ParseResult<T> {
+        Err(make_error(position, message))
     }
-    fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+    // record a problem that tolerant mode recovered from
+    fn report(&mut self, position: dom::SourcePosition, message: &str) {
+        self.errors.push(make_error(position, message));
     }
-    fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+
+    // -------- reading tokens --------
+
+    // the next token, without consuming it
+    fn peek(&mut self) -> ParseResult<Option<&Token>> {
+        if self.peeked.is_none() {
+            // the tokenizer needs to know whether CDATA sections are allowed
+            // and whether <title> and <style> hold raw text
+            self.tokenizer.set_foreign_content(self.foreign_context());
+            if let Some(token) = self.tokenizer.next_token() {
+                let source = self.tokenizer.last_source().unwrap();
+                self.peeked = Some((token, source));
+            }
+            self.take_tokenizer_errors()?;
+        }
+        Ok(self.peeked.as_ref().map(|(token, _)| token))
     }
-    fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
-        cur_char
+    // where the peeked token was found
+    fn peek_source(&self) -> dom::SourceRange {
+        self.peeked.as_ref().map(|(_, source)| *source).unwrap()
     }
-    fn consume_while<F>(&mut self, test: F) -> String
-            where F: Fn(char) -> bool {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
-            result.push(self.consume_char());
+    fn next_token(&mut self) -> ParseResult<Option<(Token, dom::SourceRange)>> {
+        self.peek()?;
+        let next = self.peeked.take();
+        if let Some((_, source)) = &next {
+            self.last_end = source.end;
         }
-        result
+        Ok(next)
+    }
+    // the peeked token is an end tag for `tag_name`
+    fn at_end_tag(&mut self, tag_name: &str, ignore_case: bool) -> ParseResult<bool> {
+        Ok(match self.peek()? {
+            Some(Token::EndTag(tag)) if ignore_case => tag.name.eq_ignore_ascii_case(tag_name),
+            Some(Token::EndTag(tag)) => tag.name == tag_name,
+            _ => false,
+        })
     }
-    fn partial_consume_while<F>(&mut self, test: F, filter: F) -> String
-            where F: Fn(char) -> bool {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
-            let c = self.consume_char();
-            if filter(c) {
-                result.push(c);
+
+    // strict mode fails on the first problem the tokenizer met, tolerant mode
+    // keeps them with its own
+    fn take_tokenizer_errors(&mut self) -> ParseResult<()> {
+        let new_errors = &self.tokenizer.errors()[self.tokenizer_errors..];
+        self.tokenizer_errors += new_errors.len();
+        if let Some(err) = new_errors.first() {
+            if !self.tolerant {
+                return Err(err.clone());
             }
+            self.errors.extend_from_slice(new_errors);
         }
-        result
+        Ok(())
+    }
+
+    // -------- building the tree --------
+
+    // the node for the next token; None for input that ends inside a tag
+    fn parse_node(&mut self) -> ParseResult<Option<dom::Node>> {
+        let (token, source) = match self.next_token()? {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let node = match token {
+            Token::Text(text) => dom::text(text),
+            Token::Comment(comment) => dom::comment(comment),
+            Token::Doctype { doctype, force_quirks } => {
+                // whatever a broken doctype says, it can't be trusted
+                self.force_quirks |= force_quirks;
+                dom::doctype(doctype.name, doctype.public_id, doctype.system_id)
+            }
+            Token::CData(data) => dom::cdata(data),
+            Token::ProcessingInstruction(target, data) if self.tolerant => {
+                // html only knows these as bogus comments
+                self.report(source.start, "unexpected processing instruction");
+                if data.is_empty() {
+                    dom::comment(format!("?{}?", target))
+                } else {
+                    dom::comment(format!("?{} {}?", target, data))
+                }
+            }
+            Token::ProcessingInstruction(target, data) => dom::processing_instruction(target, data),
+            Token::StartTag(tag) => self.parse_element(tag, source)?,
+            Token::EndTag(_) => unreachable!("end tags are handled by parse_nodes"),
+        };
+        let mut node = node;
+        node.source = Some(dom::SourceRange { start: source.start, end: self.last_end });
+        Ok(Some(node))
     }
 
-    fn consume_until(&mut self, _str: &str) -> String {
-        let mut result = String::new();
-        while !self.eof() && !self.starts_with(_str) {
-            result.push(self.consume_char());
+    fn parse_element(&mut self, tag: Tag, source: dom::SourceRange) -> ParseResult<dom::Node> {
+        let start = source.start;
+        let Tag { name, attributes, self_closing } = tag;
+        let namespace = self.namespace_for(&name);
+        // names come lowercased in tolerant mode, but svg has some in camel case
+        let tag_name = if self.tolerant { foreign::adjust_tag_name(namespace, name) } else { name };
+        let elem = self.element_data(namespace, tag_name.clone(), attributes);
+
+        if namespace == dom::Namespace::Html && self.void_elements.contains(&tag_name.to_ascii_lowercase()) {
+            self.skip_void_end_tag(&tag_name)?;
+            return Ok(element(elem, Vec::new()));
         }
-        result
+        if self_closing && namespace != dom::Namespace::Html {
+            // svg and mathml elements may close themselves like xml
+            return Ok(element(elem, Vec::new()));
+        }
+        if self_closing {
+            // html ignores the slash: the element stays open
+            let message = format!("self-closing syntax on non-void element <{}>", tag_name);
+            let slash = dom::SourcePosition {
+                offset: source.end.offset - "/>".len(),
+                line: source.end.line,
+                column: source.end.column - "/>".len(),
+            };
+            if !self.tolerant {
+                return self.error_at(slash, &message);
+            }
+            self.report(slash, &message);
+        }
+
+        if SKIPS_LEADING_NEWLINE.contains(&tag_name.to_ascii_lowercase().as_str()) {
+            self.skip_leading_newline()?;
+        }
+        let children = if self.text_only_content(&tag_name).is_some() {
+            // the tokenizer reads the whole content as one text token
+            let mut children = Vec::new();
+            if let Some(Token::Text(_)) = self.peek()? {
+                if let Some((Token::Text(text), source)) = self.next_token()? {
+                    let mut node = dom::text(text);
+                    node.source = Some(source);
+                    children.push(node);
+                }
+            }
+            children
+        } else {
+            self.open_elements.push(tag_name.clone());
+            self.open_namespaces.push(namespace);
+            let result = self.parse_nodes();
+            self.open_elements.pop();
+            self.open_namespaces.pop();
+            result?
+        };
+
+        if self.tolerant {
+            self.close_element(start, &tag_name)?;
+            return Ok(element(elem, children));
+        }
+        if self.peek()?.is_none() {
+            return self.error_at(start, &format!("unclosed element <{}>", tag_name));
+        }
+        if !self.at_end_tag(&tag_name, false)? {
+            let close_name = match self.peek()? {
+                Some(Token::EndTag(tag)) => tag.name.clone(),
+                _ => String::new(),
+            };
+            return self.error_at(self.peek_source().start,
+                &format!("mismatched close tag: expected </{}> but found </{}>", tag_name, close_name));
+        }
+        self.next_token()?;
+
+        Ok(element(elem, children))
     }
 
-    fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+    // drop a newline at the start of the next text token
+    fn skip_leading_newline(&mut self) -> ParseResult<()> {
+        self.peek()?;
+        if let Some((Token::Text(text), source)) = &mut self.peeked {
+            let newline = if text.starts_with("\r\n") { "\r\n" } else if text.starts_with('\n') { "\n" } else { "" };
+            text.replace_range(..newline.len(), "");
+            source.start = tokenizer::advance(source.start, newline);
+            if text.is_empty() {
+                self.last_end = source.end;
+                self.peeked = None;
+            }
+        }
+        Ok(())
     }
 
-    fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => true,
-            _ => false
-        })
+    // an element without its children, with the attributes of its tag and
+    // where each one was found
+    fn element_data(&mut self, namespace: dom::Namespace, tag_name: String, attributes: Vec<Attribute>) -> dom::ElementData {
+        let mut attrs = dom::AttrMap::new();
+        let mut sources = HashMap::new();
+        let mut namespaces = HashMap::new();
+        for attr in attributes {
+            let name = if self.tolerant { foreign::adjust_attribute_name(namespace, attr.name) } else { attr.name };
+            // the first occurrence of an attribute wins
+            match attrs.entry(name) {
+                Entry::Occupied(entry) => self.report(attr.source.start, &format!("duplicate attribute `{}`", entry.key())),
+                Entry::Vacant(entry) => {
+                    if namespace != dom::Namespace::Html {
+                        if let Some(attr_namespace) = foreign::attribute_namespace(entry.key()) {
+                            namespaces.insert(entry.key().clone(), attr_namespace);
+                        }
+                    }
+                    sources.insert(entry.key().clone(), attr.source);
+                    entry.insert(attr.value);
+                }
+            }
+        }
+        dom::ElementData {
+            tag_name,
+            namespace,
+            attributes: attrs,
+            attribute_namespaces: namespaces,
+            attribute_sources: sources,
+        }
     }
 
-    fn parse_node(&mut self) -> dom::Node {
-        match self.next_char() {
-            '<' =>  {
-                if self.starts_with("<!--") {
-                    self.parse_comment()
-                } else {
-                    self.parse_element()
+    fn parse_nodes(&mut self) -> ParseResult<Vec<dom::Node>> {
+        let mut nodes = Vec::new();
+
+        let tolerant = self.tolerant;
+        loop {
+            match self.peek()? {
+                None => break,
+                Some(Token::EndTag(tag)) => {
+                    if !tolerant {
+                        break;
+                    }
+                    let name = tag.name.clone();
+                    if self.end_tag_closes_open_element(&name) {
+                        break;
+                    }
+                    nodes.extend(self.parse_stray_end_tag()?);
+                    continue;
                 }
-            },
-            _ => self.parse_text()
+                Some(Token::StartTag(tag)) if tolerant => {
+                    let name = tag.name.clone();
+                    if self.implied_close(&name).is_some_and(|i| i >= self.context_elements) {
+                        break;
+                    }
+                    if let Some(parent) = self.implied_parent(&name) {
+                        nodes.push(self.parse_implied_element(parent)?);
+                        continue;
+                    }
+                }
+                Some(_) => {}
+            }
+            let node = match self.parse_node()? {
+                Some(node) => node,
+                None => continue,
+            };
+            let start = node.source.unwrap().start;
+            // a doctype only makes sense before everything else
+            let misplaced_doctype = matches!(node.node_type, dom::NodeType::Doctype(_)) && (!self.open_elements.is_empty()
+                || nodes.iter().any(|n: &dom::Node| !matches!(n.node_type, dom::NodeType::Comment(_) | dom::NodeType::ProcessingInstruction(..))));
+            if misplaced_doctype {
+                if !self.tolerant {
+                    return self.error_at(start, "unexpected doctype");
+                }
+                self.report(start, "unexpected doctype");
+                continue;
+            }
+            // text split by something dropped, like a misplaced doctype, is glued back together
+            if let Some(last) = nodes.last_mut() {
+                if let (dom::NodeType::Text(last_text), dom::NodeType::Text(text)) = (&mut last.node_type, &node.node_type) {
+                    last_text.push_str(text);
+                    if let (Some(last_source), Some(source)) = (&mut last.source, node.source) {
+                        last_source.end = source.end;
+                    }
+                    continue;
+                }
+            }
+            nodes.push(node);
         }
+
+        Ok(nodes)
     }
 
-    fn parse_text(&mut self) -> dom::Node {
-        self.consume_whitespace();
-        dom::text(self.consume_while(|c| c != '<'))
+    // for elements whose content can't hold markup, whether references in it
+    // are decoded; None for all other elements
+    fn text_only_content(&self, tag_name: &str) -> Option<bool> {
+        let name = tag_name.to_ascii_lowercase();
+        if self.in_foreign_content(&name) {
+            None
+        } else if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            Some(false)
+        } else if RCDATA_ELEMENTS.contains(&name.as_str()) {
+            Some(true)
+        } else {
+            None
+        }
     }
 
-    fn parse_comment(&mut self) -> dom::Node {
-        assert!(self.consume_char() == '<');
-        assert!(self.consume_char() == '!');
-        assert!(self.consume_char() == '-');
-        assert!(self.consume_char() == '-');
+    // an end tag right after a void element, as in `<br></br>`, is dropped
+    fn skip_void_end_tag(&mut self, tag_name: &str) -> ParseResult<()> {
+        if !self.at_end_tag(tag_name, true)? {
+            return Ok(());
+        }
+        let start = self.peek_source().start;
+        self.next_token()?;
+        if self.tolerant {
+            self.report(start, &format!("end tag for void element <{}>", tag_name));
+        }
+        Ok(())
+    }
 
-        let res = dom::comment(self.consume_until("-->"));
+    // whether `tag_name` starts or is nested in svg or mathml, outside of the
+    // points where those embed html again
+    fn in_foreign_content(&self, tag_name: &str) -> bool {
+        self.namespace_for(tag_name) != dom::Namespace::Html
+    }
+    // whether the content of the current element is svg or mathml
+    fn foreign_context(&self) -> bool {
+        self.child_namespace() != dom::Namespace::Html
+    }
 
-        assert!(self.consume_char() == '-');
-        assert!(self.consume_char() == '-');
-        assert!(self.consume_char() == '>');
+    // the namespace of the element `tag_name` if it started here
+    fn namespace_for(&self, tag_name: &str) -> dom::Namespace {
+        let tag_name = tag_name.to_ascii_lowercase();
+        let parent = self.open_elements.last().map(|name| name.to_ascii_lowercase());
+        match (self.open_namespaces.last(), parent.as_deref(), &tag_name[..]) {
+            // a few mathml elements may hold svg, or stay mathml inside text
+            (Some(dom::Namespace::MathMl), Some("annotation-xml"), "svg") => dom::Namespace::Svg,
+            (Some(dom::Namespace::MathMl), Some("mi" | "mo" | "mn" | "ms" | "mtext"), "mglyph" | "malignmark") => dom::Namespace::MathMl,
+            _ => match self.child_namespace() {
+                dom::Namespace::Html if tag_name == "svg" => dom::Namespace::Svg,
+                dom::Namespace::Html if tag_name == "math" => dom::Namespace::MathMl,
+                namespace => namespace,
+            },
+        }
+    }
+    // the namespace of the current element's children: its own, except for
+    // the elements where svg and mathml embed html again
+    fn child_namespace(&self) -> dom::Namespace {
+        let namespace = match self.open_namespaces.last() {
+            Some(namespace) => *namespace,
+            None => return dom::Namespace::Html,
+        };
+        let name = self.open_elements.last().unwrap().to_ascii_lowercase();
+        match (namespace, &name[..]) {
+            (dom::Namespace::Svg, "foreignobject" | "desc" | "title") => dom::Namespace::Html,
+            (dom::Namespace::MathMl, "mi" | "mo" | "mn" | "ms" | "mtext") => dom::Namespace::Html,
+            (namespace, _) => namespace,
+        }
+    }
+
+    // -------- tolerant tree construction --------
 
-        return res;
+    // finish an element whose children have been parsed: consume its end tag
+    // if that is what comes next, otherwise it is closed implicitly
+    fn close_element(&mut self, start: dom::SourcePosition, tag_name: &str) -> ParseResult<()> {
+        if self.at_end_tag(tag_name, true)? {
+            self.next_token()?;
+        } else if !OPTIONAL_END_TAGS.contains(&tag_name) {
+            self.report(start, &format!("unclosed element <{}>", tag_name));
+        }
+        Ok(())
     }
 
-    fn parse_element(&mut self) -> dom::Node {
-        // step1.1 parse the tag_name
-        assert!(self.consume_char() == '<');
-        let tag_name = self.parse_tag_name();
+    // whether the end tag `name` belongs to an element being parsed
+    fn end_tag_closes_open_element(&self, name: &str) -> bool {
+        // </body> and </html> are ignored: content after them still goes into the body
+        if name == "body" || name == "html" {
+            return false;
+        }
+        self.open_elements[self.context_elements..].iter().any(|open| open.eq_ignore_ascii_case(name))
+    }
 
-        // step2. parse attributes
-        self.consume_whitespace();
-        let attrs = self.parse_attributes();
-        
-        // step3. parse sub nodes
-        let children;
-        self.consume_whitespace();
-        if self.open_tags.contains(&tag_name) && self.starts_with("/>") {
-            assert!(self.consume_char() == '/');
-            assert!(self.consume_char() == '>');
-            return dom::elem(tag_name, attrs, Vec::new());
-        } else {
-            assert!(self.consume_char() == '>');
-            children = self.parse_nodes();
-            assert!(self.consume_char() == '<');
-            assert!(self.consume_char() == '/');
-            assert!(self.parse_tag_name() == tag_name);
-            assert!(self.consume_char() == '>');
+    // an end tag that matches no open element: dropped, except for the
+    // quirky </br> and </p> which browsers turn into elements
+    fn parse_stray_end_tag(&mut self) -> ParseResult<Option<dom::Node>> {
+        let (name, source) = match self.next_token()? {
+            Some((Token::EndTag(tag), source)) => (tag.name, source),
+            _ => return Ok(None),
+        };
+        match &name[..] {
+            "body" | "html" => Ok(None),
+            "br" | "p" => {
+                self.report(source.start, &format!("end tag </{}> without a start tag", name));
+                Ok(Some(dom::elem(name, dom::AttrMap::new(), Vec::new())))
+            }
+            _ => {
+                self.report(source.start, &format!("stray end tag </{}>", name));
+                Ok(None)
+            }
         }
+    }
 
-        dom::elem(tag_name, attrs, children)
+    // search the open elements from the innermost one for any of `names`,
+    // giving up at any of `boundaries`
+    fn find_open(&self, names: &[&str], boundaries: &[&str]) -> Option<usize> {
+        for (i, name) in self.open_elements.iter().enumerate().rev() {
+            if names.contains(&name.as_str()) {
+                return Some(i);
+            }
+            if boundaries.contains(&name.as_str()) {
+                return None;
+            }
+        }
+        None
     }
 
-    fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_tag_name();
-        self.consume_whitespace();
+    // the index in open_elements of the outermost element that the start tag
+    // `tag_name` closes implicitly, like a <p> closing a previous <p>
+    fn implied_close(&self, tag_name: &str) -> Option<usize> {
+        let current = self.open_elements.last().map(|s| s.as_str());
+        let list_scope = [SCOPE_BOUNDARIES, &["ol", "ul", "menu"]].concat();
+        let table_scope = ["table", "tbody", "thead", "tfoot", "html", "template"];
 
-        assert!(self.consume_char() == '=', format!("{}", &self.input[self.pos..self.pos+40]));
-        let value = self.parse_attr_value();
-        (name, value)
+        let closed = match tag_name {
+            "li" => self.find_open(&["li"], &list_scope),
+            "dd" | "dt" => self.find_open(&["dd", "dt"], &[SCOPE_BOUNDARIES, &["dl"]].concat()),
+            "option" => self.find_open(&["option"], &[]).filter(|_| current == Some("option")),
+            "optgroup" => match current {
+                Some("option") | Some("optgroup") => self.find_open(&["optgroup", "option"], &["select"]),
+                _ => None,
+            },
+            "tr" => self.find_open(&["tr"], &table_scope),
+            "td" | "th" => self.find_open(&["td", "th"], &["tr", "table", "html", "template"]),
+            "thead" | "tbody" | "tfoot" => self.find_open(&["thead", "tbody", "tfoot", "caption", "colgroup"], &["table", "html", "template"]),
+            "caption" | "colgroup" => self.find_open(&["thead", "tbody", "tfoot", "caption", "colgroup"], &["table", "html", "template"]),
+            "body" => self.find_open(&["head"], &[]),
+            t if HEADINGS.contains(&t) => current
+                .filter(|c| HEADINGS.contains(c))
+                .map(|_| self.open_elements.len() - 1),
+            _ => None,
+        };
+
+        let closes_p = if CLOSES_P.contains(&tag_name) {
+            self.find_open(&["p"], SCOPE_BOUNDARIES)
+        } else {
+            None
+        };
+
+        match (closed, closes_p) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
     }
-    fn parse_attr_value(&mut self) -> String {
-        let open_quote = self.consume_char();
-        assert!(open_quote=='"' || open_quote=='\'');
-        let value = self.consume_while(|c| c != open_quote);
-        assert!(self.consume_char() == open_quote);
-        value
+
+    // the element browsers insert between the current element and the start
+    // tag `tag_name`, like the <tbody> between a <table> and a <tr>
+    fn implied_parent(&self, tag_name: &str) -> Option<&'static str> {
+        match (self.open_elements.last().map(|s| s.as_str()), tag_name) {
+            (Some("table"), "tr") | (Some("table"), "td") | (Some("table"), "th") => Some("tbody"),
+            (Some("tbody"), "td") | (Some("thead"), "td") | (Some("tfoot"), "td") => Some("tr"),
+            (Some("tbody"), "th") | (Some("thead"), "th") | (Some("tfoot"), "th") => Some("tr"),
+            _ => None,
+        }
     }
 
-    fn parse_attributes(&mut self) -> dom::AttrMap {
-        let mut attrs = dom::AttrMap::new();
-        loop {
-            self.consume_whitespace();
-            if self.next_char() == '>' || self.next_char() == '/' {
-                break;
-            } 
-            let (name, value) = self.parse_attr();
-            attrs.insert(name, value);
-        };
-        attrs
+    fn parse_implied_element(&mut self, tag_name: &str) -> ParseResult<dom::Node> {
+        self.open_elements.push(tag_name.to_string());
+        self.open_namespaces.push(dom::Namespace::Html);
+        let result = self.parse_nodes();
+        self.open_elements.pop();
+        self.open_namespaces.pop();
+        let children = result?;
+        let start = self.last_end;
+        self.close_element(start, tag_name)?;
+        Ok(dom::elem(tag_name.to_string(), dom::AttrMap::new(), children))
     }
+}
 
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
-        let mut nodes = Vec::new();
+fn make_error(position: dom::SourcePosition, message: &str) -> ParseError {
+    ParseError {
+        offset: position.offset,
+        line: position.line,
+        column: position.column,
+        message: message.to_string(),
+    }
+}
 
-        loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
+/// Parse `source` as the content of `context`, like setting `innerHTML`,
+/// recovering from malformed markup the way browsers do.
+pub fn parse_fragment<S: ToString>(context: &dom::ElementData, source: S) -> Vec<dom::Node> {
+    let mut parser = Parser::new(source).tolerant();
+    parser.parse_fragment(context).expect("tolerant parsing recovers from every error")
+}
+
+fn is_whitespace_text(node: &dom::Node) -> bool {
+    matches!(&node.node_type, dom::NodeType::Text(text) if text.chars().all(|c| c.is_ascii_whitespace()))
+}
+
+// arrange the top level nodes of a tolerant parse into
+// <html><head>...</head><body>...</body></html>
+fn build_document(nodes: Vec<dom::Node>) -> dom::Node {
+    fn element_name(node: &dom::Node) -> Option<&str> {
+        match &node.node_type {
+            dom::NodeType::Element(elem) => Some(&elem.tag_name),
+            _ => None,
+        }
+    }
+    // repeated <html>, <head> and <body> tags are merged into one element,
+    // keeping the attributes and the source of the first one written
+    fn merge(into: &mut dom::Node, node: dom::Node) -> Vec<dom::Node> {
+        if let (dom::NodeType::Element(into_elem), dom::NodeType::Element(elem)) = (&mut into.node_type, node.node_type) {
+            for (name, value) in elem.attributes {
+                if let Some(source) = elem.attribute_sources.get(&name) {
+                    into_elem.attribute_sources.entry(name.clone()).or_insert(*source);
+                }
+                into_elem.attributes.entry(name).or_insert(value);
             }
-            nodes.push(self.parse_node());
         }
+        into.source = into.source.or(node.source);
+        node.children
+    }
+
+    let mut html = dom::elem("html".to_string(), dom::AttrMap::new(), Vec::new());
+    let mut flat = Vec::new();
+    for node in nodes {
+        if element_name(&node) == Some("html") {
+            flat.extend(merge(&mut html, node));
+        } else {
+            flat.push(node);
+        }
+    }
 
-        nodes
+    let mut head_elem = dom::elem("head".to_string(), dom::AttrMap::new(), Vec::new());
+    let mut body_elem = dom::elem("body".to_string(), dom::AttrMap::new(), Vec::new());
+    let mut head = Vec::new();
+    let mut body = Vec::new();
+    let mut in_body = false;
+    for node in flat {
+        match element_name(&node) {
+            Some("head") if !in_body => head.extend(merge(&mut head_elem, node)),
+            Some("body") => {
+                in_body = true;
+                body.extend(merge(&mut body_elem, node));
+            }
+            Some(name) if !in_body && HEAD_CONTENT.contains(&name) => head.push(node),
+            None if !in_body && matches!(node.node_type, dom::NodeType::Comment(_)) => head.push(node),
+            // white space outside of <head> and <body>, like the newlines
+            // between the tags, goes nowhere
+            None if !in_body && is_whitespace_text(&node) => {}
+            _ => {
+                in_body = true;
+                body.push(node);
+            }
+        }
     }
+
+    head_elem.children = head;
+    body_elem.children = body;
+    html.children = vec![head_elem, body_elem];
+    html
+}
+
+fn element(elem: dom::ElementData, children: Vec<dom::Node>) -> dom::Node {
+    dom::Node { children, node_type: dom::NodeType::Element(elem), source: None }
 }
 
 #[cfg(test)]
@@ -194,42 +779,509 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_basic() {
-        let mut parser = Parser::new(String::from("Hello, Parser"));
-        assert_eq!(parser.next_char(), 'H');
-        assert_eq!(parser.pos, 0);
+    fn test_parse_all() {
+        let content = "<html lang=\"en\" class='all'><!--html_comment--><body><!--body_comment-->fuck</body></html>";
+        let html = Parser::new(content.to_owned()).parse().unwrap();
+
+        assert_eq!(html.get_attribute("lang"), Some("en".to_owned()));
+        assert_eq!(html.get_attribute("class"), Some("all".to_owned()));
+        assert_eq!(html.get_attribute("no_attr"), None);
+    }
+
+    #[test]
+    fn test_character_references() {
+        let html = Parser::new("<p title='a &quot;b&quot; &amp; c'>1 &lt; 2 &amp;&amp; &#x1F600;&nbsp;&copy 2014</p>").parse().unwrap();
+        assert_eq!(html.get_attribute("title"), Some("a \"b\" & c".to_owned()));
+        assert_eq!(tag(&html.children[0]), "1 < 2 && \u{1F600}\u{a0}\u{a9} 2014");
+
+        let html = Parser::new("<a href='/q?x=1&copy=2&amp;lang=en'>link</a>").parse().unwrap();
+        assert_eq!(html.get_attribute("href"), Some("/q?x=1&copy=2&lang=en".to_owned()));
+
+        // Display escapes what was decoded, so parsing its output gives the same tree
+        let printed = html.to_string();
+        assert!(printed.contains("href=\"/q?x=1&amp;copy=2&amp;lang=en\""));
+        let reparsed = Parser::new(printed).parse().unwrap();
+        assert_eq!(reparsed.get_attribute("href"), html.get_attribute("href"));
+
+        let html = Parser::new("<p>a &lt;b&gt; &amp; \"c\"&nbsp;</p>").parse().unwrap();
+        let printed = html.to_string();
+        assert_eq!(printed, "<p>a &lt;b&gt; &amp; \"c\"&nbsp;</p>");
+        let reparsed = Parser::new(printed).parse().unwrap();
+        assert_eq!(tag(&reparsed.children[0]), tag(&html.children[0]));
+    }
+
+    #[test]
+    fn test_attribute_forms() {
+        let source = "<input disabled type=checkbox data-x=foo xml:lang=\"en\" v-on:click='go()' ng.model=a_b checked>";
+        let mut parser = Parser::new(source);
+        let html = parser.parse().unwrap();
+        assert_eq!(html.get_attribute("disabled"), Some("".to_owned()));
+        assert_eq!(html.get_attribute("checked"), Some("".to_owned()));
+        assert_eq!(html.get_attribute("type"), Some("checkbox".to_owned()));
+        assert_eq!(html.get_attribute("data-x"), Some("foo".to_owned()));
+        assert_eq!(html.get_attribute("xml:lang"), Some("en".to_owned()));
+        assert_eq!(html.get_attribute("v-on:click"), Some("go()".to_owned()));
+        assert_eq!(html.get_attribute("ng.model"), Some("a_b".to_owned()));
+        assert!(parser.errors().is_empty());
+
+        let html = Parser::new("<td colspan=2 rowspan = 3 nowrap>x</td>").parse().unwrap();
+        assert_eq!(html.get_attribute("colspan"), Some("2".to_owned()));
+        assert_eq!(html.get_attribute("rowspan"), Some("3".to_owned()));
+        assert_eq!(html.get_attribute("nowrap"), Some("".to_owned()));
+
+        // unquoted values may hold slashes and references
+        let html = Parser::new("<div><a href=/docs/?a=1&amp;b=2/>x</a></div>").parse().unwrap();
+        assert_eq!(html.children[0].get_attribute("href"), Some("/docs/?a=1&b=2/".to_owned()));
+        let html = Parser::new("<img src=a.png/>").parse().unwrap();
+        assert_eq!(html.get_attribute("src"), Some("a.png/".to_owned()));
+        let html = Parser::new("<img src=a.png />").parse().unwrap();
+        assert_eq!(html.get_attribute("src"), Some("a.png".to_owned()));
+    }
+
+    #[test]
+    fn test_duplicate_attributes() {
+        let mut parser = Parser::new("<div id=first class=a id=second ID=third></div>");
+        let html = parser.parse().unwrap();
+        assert_eq!(html.get_attribute("id"), Some("first".to_owned()));
+        assert_eq!(html.get_attribute("ID"), Some("third".to_owned()));
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.errors()[0].message, "duplicate attribute `id`");
+        assert_eq!(parser.errors()[0].offset, 22);
+
+        // attribute names are case-insensitive in tolerant mode
+        let (html, errors) = parse_tolerant("<div ID=first id=second \"stray>x</div>");
+        let div = &html.children[1].children[0];
+        assert_eq!(div.get_attribute("id"), Some("first".to_owned()));
+        assert_eq!(div.get_attribute("stray"), Some("".to_owned()));
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        let source = "<div><script>if (a < b && c > d) { s = '<p>'; }</script><style>a > b { color: red }</style></div>";
+        let html = Parser::new(source).parse().unwrap();
+        assert_eq!(shape(&html), "div(script(if (a < b && c > d) { s = '<p>'; }),style(a > b { color: red }))");
+
+        // only the matching end tag ends the content, which in strict mode
+        // must also match the case of the start tag
+        let error = Parser::new("<script>document.write('</div>')</SCRIPT ></script>").parse().unwrap_err();
+        assert_eq!(error.message, "mismatched close tag: expected </script> but found </SCRIPT>");
+        assert_eq!(error.offset, 32);
+        let (html, _) = parse_tolerant("<script>x = '</div>' + '</scripts>'</SCRIPT ><p>a");
+        assert_eq!(shape(&html), "html(head(script(x = '</div>' + '</scripts>')),body(p(a)))");
+
+        let html = Parser::new("<style></style>").parse().unwrap();
+        assert!(html.children.is_empty());
+
+        // character references are left alone in raw text
+        let html = Parser::new("<script>a &amp;&amp; b</script>").parse().unwrap();
+        assert_eq!(tag(&html.children[0]), "a &amp;&amp; b");
+    }
+
+    #[test]
+    fn test_rcdata_elements() {
+        let html = Parser::new("<textarea><b>bold</b> &amp; &lt;i&gt;</textarea>").parse().unwrap();
+        assert_eq!(shape(&html), "textarea(<b>bold</b> & <i>)");
+
+        let (html, _) = parse_tolerant("<title>A <em>b</em> &amp; c</title><p>x");
+        assert_eq!(shape(&html), "html(head(title(A <em>b</em> & c)),body(p(x)))");
 
-        assert_eq!(parser.starts_with("Hell "), false);
-        assert_eq!(parser.starts_with("Hell"), true);
+        let (html, errors) = parse_tolerant("<textarea>never closed <p>");
+        assert_eq!(shape(&html), "html(head,body(textarea(never closed <p>)))");
+        assert_eq!(errors[0].message, "unclosed element <textarea>");
 
-        let mut tmp = String::new();
-        while !parser.eof() {
-            tmp.push(parser.consume_char());
+        // svg has its own <title> and <style> that hold markup
+        let html = Parser::new("<svg><title><tspan>t</tspan></title></svg>").parse().unwrap();
+        assert_eq!(shape(&html), "svg(title(tspan(t)))");
+    }
+
+    #[test]
+    fn test_doctype() {
+        let doc = Parser::new("<!DOCTYPE html>\n<!-- top --><html><body>x</body></html>\n<!-- end -->").parse_document().unwrap();
+        assert_eq!(shape(&doc), "#document(#doctype,#comment,html(body(x)),#comment)");
+        assert_eq!(doc.quirks_mode(), Some(dom::QuirksMode::NoQuirks));
+        assert!(matches!(&doc.children[0].node_type, dom::NodeType::Doctype(d) if d.name == "html" && d.public_id.is_none()));
+
+        // parse skips everything around the root
+        let html = Parser::new("<!doctype HTML><!-- top --><html><body>x</body></html>").parse().unwrap();
+        assert_eq!(shape(&html), "html(body(x))");
+
+        let source = "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" 'http://www.w3.org/TR/html4/loose.dtd'><p>x</p>";
+        let doc = Parser::new(source).parse_document().unwrap();
+        assert_eq!(doc.quirks_mode(), Some(dom::QuirksMode::LimitedQuirks));
+        match &doc.children[0].node_type {
+            dom::NodeType::Doctype(doctype) => {
+                assert_eq!(doctype.public_id.as_deref(), Some("-//W3C//DTD HTML 4.01 Transitional//EN"));
+                assert_eq!(doctype.system_id.as_deref(), Some("http://www.w3.org/TR/html4/loose.dtd"));
+            }
+            _ => panic!("expected a doctype"),
         }
-        assert_eq!(parser.pos, tmp.len());
-        assert_eq!(parser.input, tmp);
-        println!("{}", tmp);
+
+        let doc = Parser::new("<p>no doctype</p>").parse_document().unwrap();
+        assert_eq!(doc.quirks_mode(), Some(dom::QuirksMode::Quirks));
+        let doc = Parser::new("<!DOCTYPE html SYSTEM \"about:legacy-compat\"><p>x</p>").parse_document().unwrap();
+        assert_eq!(doc.quirks_mode(), Some(dom::QuirksMode::NoQuirks));
+
+        let err = Parser::new("<!DOCTYPE html PUBLIC nope><p>x</p>").parse_document().unwrap_err();
+        assert_eq!(err.message, "expected a quoted doctype identifier");
+        let err = Parser::new("<p><!DOCTYPE html></p>").parse_document().unwrap_err();
+        assert_eq!(err.message, "unexpected doctype");
+
+        // a broken doctype always means quirks mode
+        let mut parser = Parser::new("<!DOCTYPE html PUBLIC nope><p>x").tolerant();
+        let doc = parser.parse_document().unwrap();
+        assert_eq!(doc.quirks_mode(), Some(dom::QuirksMode::Quirks));
+        assert_eq!(shape(&doc), "#document(#doctype,html(head,body(p(x))))");
+        let mut parser = Parser::new("<!DOCTYPE html><p><!DOCTYPE html>x").tolerant();
+        let doc = parser.parse_document().unwrap();
+        assert_eq!(shape(&doc), "#document(#doctype,html(head,body(p(x))))");
+        assert_eq!(parser.errors().len(), 1);
     }
 
     #[test]
-    fn test_parse_tag_name() {
-        let mut parser = Parser::new("<html_body>");
+    fn test_cdata_and_processing_instructions() {
+        let html = Parser::new("<svg><script><![CDATA[if (a < b) {}]]></script></svg>").parse().unwrap();
+        assert_eq!(shape(&html), "svg(script(#cdata))");
+        assert!(matches!(&html.children[0].children[0].node_type, dom::NodeType::CData(d) if d == "if (a < b) {}"));
+
+        // outside svg and mathml a CDATA section is a bogus comment
+        assert!(Parser::new("<div><![CDATA[x]]></div>").parse().is_err());
+        let (html, _) = parse_tolerant("<div><![CDATA[x]]></div>");
+        assert!(matches!(&html.children[1].children[0].children[0].node_type, dom::NodeType::Comment(c) if c == "[CDATA[x]]"));
 
-        parser.parse_tag_name();
-        assert_eq!(parser.pos, 0);
+        let doc = Parser::new("<?xml version=\"1.0\"?><html></html>").parse_document().unwrap();
+        assert_eq!(shape(&doc), "#document(#pi,html)");
+        assert!(matches!(&doc.children[0].node_type, dom::NodeType::ProcessingInstruction(t, d) if t == "xml" && d == "version=\"1.0\""));
 
-        assert_eq!(parser.consume_char(), '<');
-        let tag_name = parser.parse_tag_name();
-        assert_eq!(tag_name, "html");
+        let (html, _) = parse_tolerant("<p><?php echo 1 ?></p>");
+        assert!(matches!(&html.children[1].children[0].children[0].node_type, dom::NodeType::Comment(c) if c == "?php echo 1 ?"));
     }
 
     #[test]
-    fn test_parse_all() {
-        let content = "<html lang=\"en\" class='all'><!--html_comment--><body><!--body_comment-->fuck</body></html>";
-        let html = Parser::new(content.to_owned()).parse();
+    fn test_source_positions() {
+        let source = "<div id='a'>\n  <!-- c -->\n  <p class=x\n     title=\"é\">hé llo</p></div>";
+        let html = Parser::new(source).parse().unwrap();
+        let range = html.source.unwrap();
+        assert_eq!((range.start.offset, range.end.offset), (0, source.len()));
+        assert_eq!((range.start.line, range.start.column), (1, 1));
+        assert_eq!((range.end.line, range.end.column), (4, 32));
+
+        let id = html.attribute_source("id").unwrap();
+        assert_eq!(&source[id.start.offset..id.end.offset], "id='a'");
+
+        let comment = html.children[1].source.unwrap();
+        assert_eq!(&source[comment.start.offset..comment.end.offset], "<!-- c -->");
+        assert_eq!((comment.start.line, comment.start.column), (2, 3));
+
+        let p = &html.children[3];
+        let title = p.attribute_source("title").unwrap();
+        assert_eq!((title.start.line, title.start.column), (4, 6));
+        assert_eq!((title.end.line, title.end.column), (4, 15));
+        let text = p.children[0].source.unwrap();
+        assert_eq!(&source[text.start.offset..text.end.offset], "hé llo");
+        assert_eq!((text.start.line, text.start.column, text.end.column), (4, 16, 22));
+
+        // nodes that the parser made up have no source
+        let (html, _) = parse_tolerant("<table><tr><td>x</table>");
+        assert!(html.source.is_none());
+        let table = &html.children[1].children[0];
+        assert!(table.source.is_some());
+        assert!(table.children[0].source.is_none());
+        let td = &table.children[0].children[0].children[0];
+        assert_eq!(td.source.unwrap().start.offset, 11);
 
+        let (html, _) = parse_tolerant("<html lang=en><body class=b>x</body></html>");
+        assert_eq!(html.source.unwrap().start.offset, 0);
+        assert_eq!(html.attribute_source("lang").unwrap().start.offset, 6);
+        assert_eq!(html.children[1].source.unwrap().start.offset, 14);
+    }
+
+    #[test]
+    fn test_whitespace_text() {
+        let html = Parser::new("<p>\n  <a>one</a> <b>two</b>\tthree\n</p>").parse().unwrap();
+        assert_eq!(shape(&html), "p(\n  ,a(one), ,b(two),\tthree\n)");
+
+        // a newline right after <pre> or <textarea> is dropped, the rest is kept
+        let html = Parser::new("<div><pre>\n  indented\n\n</pre><textarea>\n\nx </textarea></div>").parse().unwrap();
+        assert_eq!(shape(&html), "div(pre(  indented\n\n),textarea(\nx ))");
+        let pre = html.children[0].children[0].source.unwrap();
+        assert_eq!((pre.start.line, pre.start.column), (2, 1));
+        let html = Parser::new("<pre>\n</pre>").parse().unwrap();
+        assert!(html.children.is_empty());
+
+        // white space between the top level tags goes nowhere
+        let (html, _) = parse_tolerant("<html>\n<head>\n<title>t</title>\n</head>\n<body>\n<p>x</p>\n</body>\n</html>\n");
+        assert_eq!(shape(&html), "html(head(\n,title(t),\n),body(\n,p(x),\n\n\n))");
+        let doc = Parser::new("<!DOCTYPE html>\n<html></html>\n").parse_document().unwrap();
+        assert_eq!(shape(&doc), "#document(#doctype,html)");
+    }
+
+    fn element_data(tag_name: &str) -> dom::ElementData {
+        let namespace = match tag_name {
+            "svg" => dom::Namespace::Svg,
+            "math" => dom::Namespace::MathMl,
+            _ => dom::Namespace::Html,
+        };
+        match dom::elem_ns(namespace, tag_name.to_owned(), dom::AttrMap::new(), Vec::new()).node_type {
+            dom::NodeType::Element(elem) => elem,
+            _ => unreachable!(),
+        }
+    }
+
+    fn fragment_shape(context: &str, source: &str) -> String {
+        let nodes = parse_fragment(&element_data(context), source);
+        nodes.iter().map(shape).collect::<Vec<String>>().join(",")
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        assert_eq!(fragment_shape("div", "<p>one<p>two"), "p(one),p(two)");
+        assert_eq!(fragment_shape("ul", "<li>a<li>b</ul>c"), "li(a),li(bc)");
+        assert_eq!(fragment_shape("tr", "<td>1<td>2"), "td(1),td(2)");
+        assert_eq!(fragment_shape("table", "<tr><td>x"), "tbody(tr(td(x)))");
+        assert_eq!(fragment_shape("tbody", "<td>x"), "tr(td(x))");
+        // the context itself is never closed, nor implied to be
+        assert_eq!(fragment_shape("p", "a<div>b</div>"), "a,div(b)");
+        assert_eq!(fragment_shape("li", "<li>nested"), "li(nested)");
+
+        // the context decides how the content is read
+        assert_eq!(fragment_shape("textarea", "<b>x</b> &amp; </textarea>"), "<b>x</b> & </textarea>");
+        assert_eq!(fragment_shape("SCRIPT", "if (a < b) x = '&amp;'"), "if (a < b) x = '&amp;'");
+        assert_eq!(fragment_shape("svg", "<circle/><title><b>x</b></title><![CDATA[y]]>"), "circle,title(b(x)),#cdata");
+
+        let mut parser = Parser::new("<li>a</li>\n<li>b</li>");
+        let nodes = parser.parse_fragment(&element_data("ul")).unwrap();
+        assert_eq!(nodes.len(), 3);
+        let err = Parser::new("<li>a</li></ul>").parse_fragment(&element_data("ul")).unwrap_err();
+        assert_eq!(err.offset, 10);
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let mut parser = Parser::new("<!DOCTYPE html><p>a<b>b</b></p>").tolerant();
+        let document = parser.parse_tree().unwrap();
+        let root = document.root();
+        assert_eq!(document.children(root).count(), 2);
+        let html = document.last_child(root).unwrap();
+        let body = document.last_child(html).unwrap();
+        let p = document.first_child(body).unwrap();
+        let b = document.last_child(p).unwrap();
+        assert_eq!(document.element(b).unwrap().tag_name, "b");
+        let ancestors: Vec<&str> = std::iter::successors(document.parent(b), |&id| document.parent(id))
+            .filter_map(|id| document.element(id).map(|elem| elem.tag_name.as_str()))
+            .collect();
+        assert_eq!(ancestors, vec!["p", "body", "html"]);
+        assert_eq!(document.source(p).map(|source| source.start.offset), Some(15));
+        assert_eq!(document.to_node(root).to_string(), "<!DOCTYPE html><html><head></head><body><p>a<b>b</b></p></body></html>");
+    }
+
+    #[test]
+    fn test_void_elements() {
+        let html = Parser::new("<p>a<br>b<br/>c<img src='x.png'><hr></p>").parse().unwrap();
+        let names: Vec<&str> = html.children.iter().map(tag).collect();
+        assert_eq!(names, vec!["a", "br", "b", "br", "c", "img", "hr"]);
+        assert_eq!(html.children[5].get_attribute("src"), Some("x.png".to_owned()));
+
+        let html = Parser::new("<div><input type='text' /><source src='a'><wbr><BR></div>").parse().unwrap();
+        assert_eq!(shape(&html), "div(input,source,wbr,BR)");
+
+        // an end tag right after a void element is dropped
+        let html = Parser::new("<div><br></br><link rel='icon'></link></div>").parse().unwrap();
+        assert_eq!(shape(&html), "div(br,link)");
+
+        let (html, errors) = parse_tolerant("<p>a<br>b</br><img>");
+        assert_eq!(shape(&html), "html(head,body(p(a,br,b,br,img)))");
+        assert_eq!(errors.len(), 1);
+
+        // a <meta> without /> doesn't take what follows into it
+        let (html, _) = parse_tolerant("<title>t</title><meta charset='utf-8'><!--c--><p>x");
+        assert_eq!(shape(&html), "html(head(title(t),meta,#comment),body(p(x)))");
+    }
+
+    #[test]
+    fn test_self_closing_foreign_elements() {
+        let html = Parser::new("<div><svg><circle r='1'/><path d='M0'/></svg></div>").parse().unwrap();
+        assert_eq!(shape(&html), "div(svg(circle,path))");
+
+        let html = Parser::new("<math><mi>x</mi><mspace/></math>").parse().unwrap();
+        assert_eq!(shape(&html), "math(mi(x),mspace)");
+
+        // the slash means nothing on html elements
+        let err = Parser::new("<div/>text").parse().unwrap_err();
+        assert_eq!(err.message, "self-closing syntax on non-void element <div>");
+
+        let (html, errors) = parse_tolerant("<div/>text");
+        assert_eq!(shape(&html), "html(head,body(div(text)))");
+        assert_eq!(errors.len(), 2);
+
+        let (html, _) = parse_tolerant("<svg><foreignObject><div/>a</foreignObject></svg>");
+        assert_eq!(shape(&html), "html(head,body(svg(foreignObject(div(a)))))");
+    }
+
+    fn namespaces(node: &dom::Node, result: &mut Vec<(String, dom::Namespace)>) {
+        if let dom::NodeType::Element(elem) = &node.node_type {
+            result.push((elem.tag_name.clone(), elem.namespace));
+        }
+        for child in &node.children {
+            namespaces(child, result);
+        }
+    }
+
+    #[test]
+    fn test_foreign_content() {
+        use dom::Namespace::*;
+        let source = "<svg VIEWBOX='0 0 1 1'><lineargradient/><foreignobject><p>x</p></foreignobject>\
+            <a xlink:href='#c'><circle r=1 /></a></svg><math definitionurl=u><mi><b>y</b></mi></math>";
+        let (html, errors) = parse_tolerant(source);
+        assert!(errors.is_empty());
+        let body = &html.children[1];
+        let mut result = Vec::new();
+        namespaces(body, &mut result);
+        let expected = vec![
+            ("body", Html), ("svg", Svg), ("linearGradient", Svg), ("foreignObject", Svg), ("p", Html),
+            ("a", Svg), ("circle", Svg), ("math", MathMl), ("mi", MathMl), ("b", Html),
+        ];
+        assert_eq!(result, expected.into_iter().map(|(name, ns)| (name.to_string(), ns)).collect::<Vec<_>>());
+
+        let svg = &body.children[0];
+        assert_eq!(svg.get_attribute("viewBox"), Some("0 0 1 1".to_owned()));
+        match &svg.children[2].node_type {
+            dom::NodeType::Element(a) => assert_eq!(a.attribute_namespace("xlink:href"), Some(XLink)),
+            _ => panic!("expected <a>"),
+        }
+        assert_eq!(body.children[1].get_attribute("definitionURL"), Some("u".to_owned()));
+
+        // the output keeps the names and reads back the same
+        let output = svg.to_string();
+        assert!(output.contains("<linearGradient/>") && output.contains("<circle r=\"1\"/>"));
+        let (reparsed, _) = parse_tolerant(&output);
+        let mut again = Vec::new();
+        namespaces(&reparsed.children[1].children[0], &mut again);
+        assert_eq!(again, result[1..7].to_vec());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = Parser::new("<div>\n  <p>text</div>").parse().unwrap_err();
+        assert_eq!(err.offset, 15);
+        assert_eq!((err.line, err.column), (2, 10));
+        assert!(err.message.contains("mismatched close tag"));
+
+        let err = Parser::new("<div class=></div>").parse().unwrap_err();
+        assert_eq!(err.offset, 11);
+        assert_eq!(err.message, "missing attribute value");
+
+        let err = Parser::new("<div \"class\"></div>").parse().unwrap_err();
+        assert_eq!(err.message, "expected an attribute name");
+
+        let err = Parser::new("<div><p>text</p>").parse().unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert_eq!(err.message, "unclosed element <div>");
+
+        let err = Parser::new("<!-- never closed").parse().unwrap_err();
+        assert_eq!(err.message, "unterminated comment");
+
+        let err = Parser::new("<p>a</p></div>").parse().unwrap_err();
+        assert_eq!(err.offset, 8);
+
+        let err = Parser::new("<a href='x").parse().unwrap_err();
+        assert_eq!(err.message, "unterminated attribute value");
+        assert_eq!(err.to_string(), "1:9: unterminated attribute value");
+    }
+
+    fn tag(node: &dom::Node) -> &str {
+        match &node.node_type {
+            dom::NodeType::Element(elem) => &elem.tag_name,
+            dom::NodeType::Text(text) => text,
+            dom::NodeType::Comment(_) => "#comment",
+            dom::NodeType::Doctype(_) => "#doctype",
+            dom::NodeType::ProcessingInstruction(..) => "#pi",
+            dom::NodeType::CData(_) => "#cdata",
+            dom::NodeType::Document(_) => "#document",
+        }
+    }
+
+    // render a tree as nested tag names, like html(head,body(p(one),p(two)))
+    fn shape(node: &dom::Node) -> String {
+        if node.children.is_empty() {
+            return tag(node).to_string();
+        }
+        let children: Vec<String> = node.children.iter().map(shape).collect();
+        format!("{}({})", tag(node), children.join(","))
+    }
+
+    fn parse_tolerant(source: &str) -> (dom::Node, Vec<ParseError>) {
+        let mut parser = Parser::new(source).tolerant();
+        let node = parser.parse().unwrap();
+        (node, parser.errors().to_vec())
+    }
+
+    #[test]
+    fn test_tolerant_implied_end_tags() {
+        let (html, errors) = parse_tolerant("<p>one<p>two");
+        assert_eq!(shape(&html), "html(head,body(p(one),p(two)))");
+        assert!(errors.is_empty());
+
+        let (html, _) = parse_tolerant("<ul><li>a<li>b</ul><ol><li>c</ol>");
+        assert_eq!(shape(&html), "html(head,body(ul(li(a),li(b)),ol(li(c))))");
+
+        let (html, _) = parse_tolerant("<p>text<div>block</div>");
+        assert_eq!(shape(&html), "html(head,body(p(text),div(block)))");
+
+        let (html, _) = parse_tolerant("<ul><li>a<ul><li>b</ul><li>c</ul>");
+        assert_eq!(shape(&html), "html(head,body(ul(li(a,ul(li(b))),li(c))))");
+
+        let (html, _) = parse_tolerant("<dl><dt>term<dd>one<dd>two</dl>");
+        assert_eq!(shape(&html), "html(head,body(dl(dt(term),dd(one),dd(two))))");
+
+        let (html, _) = parse_tolerant("<select><option>a<option>b</select>");
+        assert_eq!(shape(&html), "html(head,body(select(option(a),option(b))))");
+
+        let (html, _) = parse_tolerant("<h1>title<h2>sub</h2>");
+        assert_eq!(shape(&html), "html(head,body(h1(title),h2(sub)))");
+    }
+
+    #[test]
+    fn test_tolerant_tables() {
+        let (html, _) = parse_tolerant("<table><tr><td>1<td>2<tr><td>3</table>");
+        assert_eq!(shape(&html), "html(head,body(table(tbody(tr(td(1),td(2)),tr(td(3))))))");
+
+        let (html, _) = parse_tolerant("<table><thead><tr><th>h<tbody><tr><td>d</table>");
+        assert_eq!(shape(&html), "html(head,body(table(thead(tr(th(h))),tbody(tr(td(d))))))");
+    }
+
+    #[test]
+    fn test_tolerant_document_structure() {
+        let (html, _) = parse_tolerant("<title>t</title><meta charset='utf-8'/><!--c--><p>x");
+        assert_eq!(shape(&html), "html(head(title(t),meta,#comment),body(p(x)))");
+
+        let (html, _) = parse_tolerant("<html lang='en'><head><title>t</title><body class='b'><p>x</body></html>");
+        assert_eq!(shape(&html), "html(head(title(t)),body(p(x)))");
         assert_eq!(html.get_attribute("lang"), Some("en".to_owned()));
-        assert_eq!(html.get_attribute("class"), Some("all".to_owned()));
-        assert_eq!(html.get_attribute("no_attr"), None);
+        assert_eq!(html.children[1].get_attribute("class"), Some("b".to_owned()));
+
+        let (html, _) = parse_tolerant("text only");
+        assert_eq!(shape(&html), "html(head,body(text only))");
+    }
+
+    #[test]
+    fn test_tolerant_recovery() {
+        let (html, errors) = parse_tolerant("<div><span>a</div>b</i>");
+        assert_eq!(shape(&html), "html(head,body(div(span(a)),b))");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "unclosed element <span>");
+        assert_eq!(errors[0].offset, 5);
+        assert_eq!(errors[1].message, "stray end tag </i>");
+
+        let (html, _) = parse_tolerant("<DIV>a</div></p>");
+        assert_eq!(shape(&html), "html(head,body(div(a),p))");
+
+        // `</` followed by anything but a letter starts a bogus comment
+        let (html, _) = parse_tolerant("<p>a < b and c</ d</p>");
+        assert_eq!(shape(&html), "html(head,body(p(a < b and c,#comment)))");
+
+        let (html, errors) = parse_tolerant("<div><!-- open");
+        assert_eq!(shape(&html), "html(head,body(div(#comment)))");
+        assert_eq!(errors.len(), 2);
     }
 }
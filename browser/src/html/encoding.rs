@@ -0,0 +1,321 @@
+// Finding out how the bytes of a document are encoded and decoding them
+// https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+use crate::dom;
+
+// how far into the document a <meta charset> is looked for
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Where the encoding of a document came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingSource {
+    ByteOrderMark,
+    // a <meta charset> or <meta http-equiv="content-type">
+    Meta,
+    // nothing said, the bytes were valid UTF-8 or taken as windows-1252
+    Guess,
+}
+
+#[derive(Debug)]
+pub struct DecodedInput {
+    pub text: String,
+    // the name the Encoding Standard gives it, like "UTF-8" or "GBK"
+    pub encoding: &'static str,
+    pub source: EncodingSource,
+    // some bytes didn't decode and were replaced with U+FFFD
+    pub had_errors: bool,
+}
+
+/// Decode a document the way browsers do: a byte order mark wins, then a
+/// `<meta>` declaration in the first 1024 bytes, otherwise UTF-8 if the
+/// bytes are valid UTF-8 and windows-1252 if not. Labels like `latin1`,
+/// `iso-8859-1` and `gb2312` map to the encodings browsers use for them.
+pub fn decode(bytes: &[u8]) -> DecodedInput {
+    let (encoding, source, bom_length) = sniff(bytes);
+    let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+    DecodedInput {
+        text: text.into_owned(),
+        encoding: encoding.name(),
+        source,
+        had_errors,
+    }
+}
+
+// the encoding of `bytes`, where it came from and how long its byte order mark is
+fn sniff(bytes: &[u8]) -> (&'static Encoding, EncodingSource, usize) {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return (encoding, EncodingSource::ByteOrderMark, bom_length);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LENGTH)]) {
+        return (encoding, EncodingSource::Meta, 0);
+    }
+    let encoding = if std::str::from_utf8(bytes).is_ok() { UTF_8 } else { WINDOWS_1252 };
+    (encoding, EncodingSource::Guess, 0)
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes.windows(pattern.len()).position(|window| window == pattern)
+}
+
+// look for a <meta> declaring the encoding, skipping comments and the
+// attributes of other tags
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            // the `--` of `<!--` may be the start of `-->`, as in `<!-->`
+            pos += 2 + find(&rest[2..], b"-->")? + 3;
+        } else if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/') {
+            pos += 5;
+            if let Some(encoding) = prescan_meta(bytes, &mut pos)? {
+                return Some(encoding);
+            }
+        } else if rest.len() > 1 && ((rest[0] == b'<' && rest[1].is_ascii_alphabetic())
+            || rest.starts_with(b"</") && rest.get(2).is_some_and(u8::is_ascii_alphabetic)) {
+            // any other tag: skip its name and attributes
+            pos += rest.iter().position(|&b| is_space(b) || b == b'>')?;
+            while get_attribute(bytes, &mut pos)?.is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += rest.iter().position(|&b| b == b'>')? + 1;
+        } else {
+            pos += 1;
+        }
+    }
+    None
+}
+
+// the encoding a <meta> declares, with `pos` just after `<meta`. The outer
+// None means the prescan ran out of bytes
+fn prescan_meta(bytes: &[u8], pos: &mut usize) -> Option<Option<&'static Encoding>> {
+    let mut seen = Vec::new();
+    let mut got_pragma = false;
+    // whether the charset came from `content`, which needs http-equiv to count
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = get_attribute(bytes, pos)? {
+        if seen.contains(&name) {
+            continue;
+        }
+        match &name[..] {
+            b"http-equiv" => got_pragma |= value == b"content-type",
+            b"content" if charset.is_none() => {
+                if let Some(label) = charset_from_content(&value) {
+                    charset = Some(Encoding::for_label(label));
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" if charset.is_none() => {
+                charset = Some(Encoding::for_label(&value));
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    let declared = match (need_pragma, charset) {
+        (Some(true), _) if !got_pragma => None,
+        (Some(_), Some(Some(encoding))) => Some(encoding),
+        _ => None,
+    };
+    Some(declared.map(adjust_declared))
+}
+
+// a document that can be read as ascii isn't UTF-16, whatever it says
+fn adjust_declared(encoding: &'static Encoding) -> &'static Encoding {
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        UTF_8
+    } else if encoding == X_USER_DEFINED {
+        WINDOWS_1252
+    } else {
+        encoding
+    }
+}
+
+/// The name of the encoding a parsed `<meta>` element declares, the way the
+/// prescan reads it.
+pub(crate) fn meta_encoding(elem: &dom::ElementData) -> Option<&'static str> {
+    let label = match elem.attributes.get("charset") {
+        Some(charset) => charset.as_bytes(),
+        None => {
+            let pragma = elem.attributes.get("http-equiv")?;
+            if !pragma.eq_ignore_ascii_case("content-type") {
+                return None;
+            }
+            charset_from_content(elem.attributes.get("content")?.as_bytes())?
+        }
+    };
+    Encoding::for_label(label).map(|encoding| adjust_declared(encoding).name())
+}
+
+// the next attribute of a tag as lowercased bytes, or None at the end of the
+// tag. The outer None means the prescan ran out of bytes
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<Option<(Vec<u8>, Vec<u8>)>> {
+    let byte = |pos: usize| bytes.get(pos).copied();
+
+    while is_space(byte(*pos)?) || byte(*pos)? == b'/' {
+        *pos += 1;
+    }
+    if byte(*pos)? == b'>' {
+        return Some(None);
+    }
+
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    loop {
+        let b = byte(*pos)?;
+        if b == b'=' && !name.is_empty() {
+            *pos += 1;
+            break;
+        } else if is_space(b) {
+            while is_space(byte(*pos)?) {
+                *pos += 1;
+            }
+            if byte(*pos)? != b'=' {
+                return Some(Some((name, value)));
+            }
+            *pos += 1;
+            break;
+        } else if b == b'/' || b == b'>' {
+            return Some(Some((name, value)));
+        }
+        name.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+
+    while is_space(byte(*pos)?) {
+        *pos += 1;
+    }
+    let b = byte(*pos)?;
+    if b == b'"' || b == b'\'' {
+        loop {
+            *pos += 1;
+            let c = byte(*pos)?;
+            if c == b {
+                *pos += 1;
+                return Some(Some((name, value)));
+            }
+            value.push(c.to_ascii_lowercase());
+        }
+    }
+    if b == b'>' {
+        return Some(Some((name, value)));
+    }
+    loop {
+        let b = byte(*pos)?;
+        if is_space(b) || b == b'>' {
+            return Some(Some((name, value)));
+        }
+        value.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+}
+
+// the label in a content attribute like `text/html; charset=gbk`
+// https://html.spec.whatwg.org/multipage/urls-and-fetching.html#extracting-character-encodings-from-meta-elements
+fn charset_from_content(content: &[u8]) -> Option<&[u8]> {
+    let mut rest = content;
+    loop {
+        let i = rest.windows(7).position(|window| window.eq_ignore_ascii_case(b"charset"))?;
+        rest = &rest[i + 7..];
+        let after_spaces = rest.iter().position(|&b| !is_space(b)).unwrap_or(rest.len());
+        if rest.get(after_spaces) == Some(&b'=') {
+            rest = &rest[after_spaces + 1..];
+            break;
+        }
+    }
+    let start = rest.iter().position(|&b| !is_space(b))?;
+    let rest = &rest[start..];
+    match rest[0] {
+        quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|&b| b == quote)?;
+            Some(&rest[1..1 + end])
+        }
+        _ => {
+            let end = rest.iter().position(|&b| is_space(b) || b == b';').unwrap_or(rest.len());
+            Some(&rest[..end])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_order_marks() {
+        let input = decode(b"\xEF\xBB\xBF<p>caf\xC3\xA9</p>");
+        assert_eq!((input.text.as_str(), input.encoding, input.source), ("<p>café</p>", "UTF-8", EncodingSource::ByteOrderMark));
+
+        let utf16: Vec<u8> = "\u{FEFF}<p>日本</p>".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        let input = decode(&utf16);
+        assert_eq!((input.text.as_str(), input.encoding), ("<p>日本</p>", "UTF-16LE"));
+        let utf16: Vec<u8> = "\u{FEFF}<p>日本</p>".encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect();
+        assert_eq!(decode(&utf16).text, "<p>日本</p>");
+
+        // the byte order mark wins over what the document says
+        let input = decode(b"\xEF\xBB\xBF<meta charset=latin1><p>\xC3\xA9</p>");
+        assert_eq!(input.text, "<meta charset=latin1><p>é</p>");
+    }
+
+    #[test]
+    fn test_meta_declarations() {
+        let input = decode(b"<!DOCTYPE html><html><head><meta charset=\"ISO-8859-1\"><title>caf\xE9</title>");
+        assert_eq!((input.encoding, input.source), ("windows-1252", EncodingSource::Meta));
+        assert!(input.text.ends_with("café</title>"));
+
+        let gbk = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=gb2312\"><p>\xD6\xD0\xCE\xC4</p>";
+        let input = decode(gbk);
+        assert_eq!(input.encoding, "GBK");
+        assert!(input.text.ends_with("<p>中文</p>"));
+
+        // content only counts along with http-equiv
+        assert_eq!(decode(b"<meta content='text/html; charset=gbk'>").source, EncodingSource::Guess);
+        // the first declaration in a meta wins
+        let input = decode(b"<meta http-equiv=content-type content='text/html; charset=gbk' charset=windows-1252>");
+        assert_eq!(input.encoding, "GBK");
+        // a meta saying UTF-16 can't be right for a document it could be read in
+        assert_eq!(decode(b"<meta charset=utf-16le>").encoding, "UTF-8");
+        // declarations in comments and attribute values are skipped
+        let input = decode(b"<!-- <meta charset=gbk> --><a title='<meta charset=gbk>'><meta charset=windows-1252>\x80");
+        assert_eq!((input.encoding, input.text.ends_with('\u{20AC}')), ("windows-1252", true));
+    }
+
+    #[test]
+    fn test_fallback() {
+        let input = decode("<p>héllo</p>".as_bytes());
+        assert_eq!((input.encoding, input.source, input.had_errors), ("UTF-8", EncodingSource::Guess, false));
+
+        // bytes that aren't UTF-8 are most likely windows-1252
+        let input = decode(b"<p>caf\xE9 \x93quoted\x94</p>");
+        assert_eq!(input.encoding, "windows-1252");
+        assert_eq!(input.text, "<p>café \u{201C}quoted\u{201D}</p>");
+
+        // bytes invalid in the declared encoding are replaced
+        let input = decode(b"<meta charset=utf-8><p>\xFFok</p>");
+        assert!(input.had_errors);
+        assert!(input.text.ends_with("<p>\u{FFFD}ok</p>"));
+        let input = decode(b"<meta charset=bogus><p>\xFF</p>");
+        assert_eq!(input.encoding, "windows-1252");
+    }
+
+    #[test]
+    fn test_charset_from_content() {
+        assert_eq!(charset_from_content(b"text/html; charset=utf-8"), Some(&b"utf-8"[..]));
+        assert_eq!(charset_from_content(b"text/html;CHARSET = 'gbk' x"), Some(&b"gbk"[..]));
+        assert_eq!(charset_from_content(b"charsetx charset=\"big5"), None);
+        assert_eq!(charset_from_content(b"text/html"), None);
+    }
+}
@@ -0,0 +1,132 @@
+// Character reference decoding, like `&amp;`, `&#169;` and `&#x1F600;`
+// https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state
+
+use super::entity_table::NAMED_REFERENCES;
+
+// the longest name in the table, `&CounterClockwiseContourIntegral;`
+const LONGEST_NAME: usize = 32;
+
+// code points 0x80..=0x9F are read as windows-1252, like old browsers did
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Replace every character reference in `s` with the text it stands for.
+/// References that don't decode are kept as written. In attribute values a
+/// legacy reference without `;` followed by `=` or an alphanumeric is not a
+/// reference, so `?a=1&copy=2` keeps its `&copy`.
+pub fn decode(s: &str, in_attribute: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_one(&rest[1..], in_attribute) {
+            Some((text, len)) => {
+                result.push_str(&text);
+                rest = &rest[1 + len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// decode the reference at the start of `s` (just after the `&`), returning
+// its text and how many bytes of `s` it used
+fn decode_one(s: &str, in_attribute: bool) -> Option<(String, usize)> {
+    if let Some(numeric) = s.strip_prefix('#') {
+        return decode_numeric(numeric).map(|(c, len)| (c.to_string(), len + 1));
+    }
+    decode_named(s, in_attribute)
+}
+
+fn decode_numeric(s: &str) -> Option<(char, usize)> {
+    let (radix, prefix) = match s.as_bytes().first() {
+        Some(b'x') | Some(b'X') => (16, 1),
+        _ => (10, 0),
+    };
+    let digits = s[prefix..].bytes().take_while(|b| (*b as char).is_digit(radix)).count();
+    if digits == 0 {
+        return None;
+    }
+    let mut len = prefix + digits;
+    // the `;` is optional, it's only an error to leave it out
+    if s[len..].starts_with(';') {
+        len += 1;
+    }
+
+    let code = u32::from_str_radix(&s[prefix..prefix + digits], radix).unwrap_or(u32::MAX);
+    let c = match code {
+        0x80..=0x9F => WINDOWS_1252[(code - 0x80) as usize],
+        0 => '\u{FFFD}',
+        _ => std::char::from_u32(code).unwrap_or('\u{FFFD}'),
+    };
+    Some((c, len))
+}
+
+fn decode_named(s: &str, in_attribute: bool) -> Option<(String, usize)> {
+    let name_len = s.bytes()
+        .take(LONGEST_NAME)
+        .take_while(|b| b.is_ascii_alphanumeric())
+        .count();
+    let with_semicolon = s[name_len..].starts_with(';');
+
+    // the longest name in the table that the input starts with wins
+    let candidates = (1..=name_len).rev().map(|len| &s[..len]);
+    let candidates = with_semicolon.then(|| &s[..name_len + 1]).into_iter().chain(candidates);
+    for candidate in candidates {
+        if let Ok(i) = NAMED_REFERENCES.binary_search_by(|(name, _)| name.cmp(&candidate)) {
+            let len = candidate.len();
+            if in_attribute && !candidate.ends_with(';') {
+                let next = s[len..].chars().next();
+                if next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+                    return None;
+                }
+            }
+            return Some((NAMED_REFERENCES[i].1.to_string(), len));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_references() {
+        assert_eq!(decode("a &amp; b &lt;c&gt;", false), "a & b <c>");
+        assert_eq!(decode("&nbsp;&copy;&CounterClockwiseContourIntegral;", false), "\u{a0}\u{a9}\u{2233}");
+        assert_eq!(decode("&NotNestedLessLess;", false), "\u{2aa1}\u{338}");
+        // legacy forms without `;`
+        assert_eq!(decode("&copy 2014 &amp", false), "\u{a9} 2014 &");
+        assert_eq!(decode("&notit;", false), "\u{ac}it;");
+        // unknown names stay as written
+        assert_eq!(decode("&bogus; & &;", false), "&bogus; & &;");
+    }
+
+    #[test]
+    fn test_numeric_references() {
+        assert_eq!(decode("&#169;&#xA9;&#Xa9", false), "\u{a9}\u{a9}\u{a9}");
+        assert_eq!(decode("&#x1F600;", false), "\u{1F600}");
+        assert_eq!(decode("&#128;&#x9F;", false), "\u{20AC}\u{178}");
+        assert_eq!(decode("&#0;&#xD800;&#x110000;&#99999999999;", false), "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}");
+        assert_eq!(decode("&#;&#x;", false), "&#;&#x;");
+    }
+
+    #[test]
+    fn test_attribute_references() {
+        assert_eq!(decode("?a=1&copy=2&amp;b=&lt;", true), "?a=1&copy=2&b=<");
+        assert_eq!(decode("&copyright", true), "&copyright");
+        assert_eq!(decode("&copyright", false), "\u{a9}right");
+        assert_eq!(decode("&copy;right", true), "\u{a9}right");
+    }
+}
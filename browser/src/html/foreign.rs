@@ -0,0 +1,101 @@
+// Names in svg and mathml, which html reads lowercased but which are
+// case-sensitive there, like `viewBox` and `foreignObject`
+// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+
+use crate::dom::Namespace;
+
+// sorted by their lowercase form
+const SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion", "animateTransform",
+    "clipPath", "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite",
+    "feConvolveMatrix", "feDiffuseLighting", "feDisplacementMap", "feDistantLight",
+    "feDropShadow", "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur",
+    "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset", "fePointLight",
+    "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence", "foreignObject", "glyphRef",
+    "linearGradient", "radialGradient", "textPath",
+];
+
+const SVG_ATTRIBUTE_NAMES: &[&str] = &[
+    "attributeName", "attributeType", "baseFrequency", "baseProfile", "calcMode",
+    "clipPathUnits", "diffuseConstant", "edgeMode", "filterUnits", "glyphRef",
+    "gradientTransform", "gradientUnits", "kernelMatrix", "kernelUnitLength", "keyPoints",
+    "keySplines", "keyTimes", "lengthAdjust", "limitingConeAngle", "markerHeight", "markerUnits",
+    "markerWidth", "maskContentUnits", "maskUnits", "numOctaves", "pathLength",
+    "patternContentUnits", "patternTransform", "patternUnits", "pointsAtX", "pointsAtY",
+    "pointsAtZ", "preserveAlpha", "preserveAspectRatio", "primitiveUnits", "refX", "refY",
+    "repeatCount", "repeatDur", "requiredExtensions", "requiredFeatures", "specularConstant",
+    "specularExponent", "spreadMethod", "startOffset", "stdDeviation", "stitchTiles",
+    "surfaceScale", "systemLanguage", "tableValues", "targetX", "targetY", "textLength",
+    "viewBox", "viewTarget", "xChannelSelector", "yChannelSelector", "zoomAndPan",
+];
+
+// attributes of svg and mathml elements that are in a namespace of their own
+const FOREIGN_ATTRIBUTES: &[(&str, Namespace)] = &[
+    ("xlink:actuate", Namespace::XLink),
+    ("xlink:arcrole", Namespace::XLink),
+    ("xlink:href", Namespace::XLink),
+    ("xlink:role", Namespace::XLink),
+    ("xlink:show", Namespace::XLink),
+    ("xlink:title", Namespace::XLink),
+    ("xlink:type", Namespace::XLink),
+    ("xml:lang", Namespace::Xml),
+    ("xml:space", Namespace::Xml),
+    ("xmlns", Namespace::Xmlns),
+    ("xmlns:xlink", Namespace::Xmlns),
+];
+
+fn lookup(names: &[&'static str], name: &str) -> Option<&'static str> {
+    names.binary_search_by(|probe| probe.to_ascii_lowercase().as_str().cmp(name))
+        .ok()
+        .map(|i| names[i])
+}
+
+/// The name of an element in `namespace`, given lowercased.
+pub fn adjust_tag_name(namespace: Namespace, name: String) -> String {
+    match namespace {
+        Namespace::Svg => lookup(SVG_TAG_NAMES, &name).map_or(name, str::to_string),
+        _ => name,
+    }
+}
+
+/// The name of an attribute of an element in `namespace`, given lowercased.
+pub fn adjust_attribute_name(namespace: Namespace, name: String) -> String {
+    match namespace {
+        Namespace::Svg => lookup(SVG_ATTRIBUTE_NAMES, &name).map_or(name, str::to_string),
+        Namespace::MathMl if name == "definitionurl" => "definitionURL".to_string(),
+        _ => name,
+    }
+}
+
+/// The namespace of an attribute of an svg or mathml element, None for
+/// attributes in no namespace.
+pub fn attribute_namespace(name: &str) -> Option<Namespace> {
+    FOREIGN_ATTRIBUTES.iter()
+        .find(|(attribute, _)| *attribute == name)
+        .map(|(_, namespace)| *namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables_are_sorted() {
+        for names in &[SVG_TAG_NAMES, SVG_ATTRIBUTE_NAMES] {
+            let lowercase: Vec<String> = names.iter().map(|name| name.to_ascii_lowercase()).collect();
+            assert!(lowercase.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn test_adjust_names() {
+        assert_eq!(adjust_tag_name(Namespace::Svg, "foreignobject".to_string()), "foreignObject");
+        assert_eq!(adjust_tag_name(Namespace::Svg, "circle".to_string()), "circle");
+        assert_eq!(adjust_tag_name(Namespace::Html, "textpath".to_string()), "textpath");
+        assert_eq!(adjust_attribute_name(Namespace::Svg, "viewbox".to_string()), "viewBox");
+        assert_eq!(adjust_attribute_name(Namespace::MathMl, "definitionurl".to_string()), "definitionURL");
+        assert_eq!(adjust_attribute_name(Namespace::MathMl, "viewbox".to_string()), "viewbox");
+        assert_eq!(attribute_namespace("xlink:href"), Some(Namespace::XLink));
+        assert_eq!(attribute_namespace("href"), None);
+    }
+}
@@ -0,0 +1,147 @@
+// Quirks mode detection from the doctype
+// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+
+use crate::dom::{DoctypeData, QuirksMode};
+
+// public identifiers of old doctypes that put a document in quirks mode,
+// matched case-insensitively as prefixes
+const QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19971010::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+const QUIRKS_PUBLIC_IDS: &[&str] = &[
+    "-//W3O//DTD W3 HTML Strict 3.0//EN//",
+    "-/W3C/DTD HTML 4.0 Transitional/EN",
+    "HTML",
+];
+const QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+// HTML 4.01 is quirky without a system identifier and limited-quirky with one
+const HTML401_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//W3C//DTD HTML 4.01 Frameset//",
+    "-//W3C//DTD HTML 4.01 Transitional//",
+];
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//W3C//DTD XHTML 1.0 Frameset//",
+    "-//W3C//DTD XHTML 1.0 Transitional//",
+];
+
+fn starts_with_any(s: &str, prefixes: &[&str]) -> bool {
+    prefixes.iter().any(|prefix| {
+        s.len() >= prefix.len() && s.is_char_boundary(prefix.len()) && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+    })
+}
+
+/// The mode a document renders in: quirks without a doctype, and for the
+/// doctypes of old HTML versions.
+pub fn quirks_mode(doctype: Option<&DoctypeData>) -> QuirksMode {
+    let doctype = match doctype {
+        Some(doctype) => doctype,
+        None => return QuirksMode::Quirks,
+    };
+    if doctype.name != "html" {
+        return QuirksMode::Quirks;
+    }
+
+    let public_id = doctype.public_id.as_deref().unwrap_or("");
+    let has_system_id = doctype.system_id.is_some();
+    let system_id = doctype.system_id.as_deref().unwrap_or("");
+
+    if QUIRKS_PUBLIC_IDS.iter().any(|id| id.eq_ignore_ascii_case(public_id))
+        || system_id.eq_ignore_ascii_case(QUIRKS_SYSTEM_ID)
+        || starts_with_any(public_id, QUIRKS_PUBLIC_ID_PREFIXES)
+        || (!has_system_id && starts_with_any(public_id, HTML401_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::Quirks
+    } else if starts_with_any(public_id, LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+        || (has_system_id && starts_with_any(public_id, HTML401_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doctype(name: &str, public_id: Option<&str>, system_id: Option<&str>) -> DoctypeData {
+        DoctypeData {
+            name: name.to_owned(),
+            public_id: public_id.map(str::to_owned),
+            system_id: system_id.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_quirks_mode() {
+        assert_eq!(quirks_mode(None), QuirksMode::Quirks);
+        assert_eq!(quirks_mode(Some(&doctype("html", None, None))), QuirksMode::NoQuirks);
+        assert_eq!(quirks_mode(Some(&doctype("svg", None, None))), QuirksMode::Quirks);
+
+        let strict = doctype("html", Some("-//W3C//DTD HTML 4.01//EN"), Some("http://www.w3.org/TR/html4/strict.dtd"));
+        assert_eq!(quirks_mode(Some(&strict)), QuirksMode::NoQuirks);
+
+        let old = doctype("html", Some("-//w3c//dtd html 3.2 final//en"), None);
+        assert_eq!(quirks_mode(Some(&old)), QuirksMode::Quirks);
+
+        let transitional = doctype("html", Some("-//W3C//DTD HTML 4.01 Transitional//EN"), None);
+        assert_eq!(quirks_mode(Some(&transitional)), QuirksMode::Quirks);
+        let transitional = doctype("html", Some("-//W3C//DTD HTML 4.01 Transitional//EN"), Some("http://www.w3.org/TR/html4/loose.dtd"));
+        assert_eq!(quirks_mode(Some(&transitional)), QuirksMode::LimitedQuirks);
+
+        let xhtml = doctype("html", Some("-//W3C//DTD XHTML 1.0 Transitional//EN"), Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd"));
+        assert_eq!(quirks_mode(Some(&xhtml)), QuirksMode::LimitedQuirks);
+    }
+}
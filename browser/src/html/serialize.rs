@@ -0,0 +1,332 @@
+// Turning a dom::Node back into markup
+// https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+use std::fmt::{self, Write};
+
+use super::{RAW_TEXT_ELEMENTS, SKIPS_LEADING_NEWLINE, VOID_ELEMENTS};
+use crate::dom;
+
+// elements whose text is written as it is, `plaintext` included though the
+// parser doesn't give it any special treatment
+const LITERAL_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "plaintext", "script", "style", "xmp"];
+// elements where white space is kept as it is when minifying or pretty-printing
+const PRESERVES_SPACE: &[&str] = &["listing", "plaintext", "pre", "textarea"];
+// elements where white space between children doesn't render, so minifying drops it
+const NO_TEXT_CONTENT: &[&str] = &[
+    "colgroup", "dl", "head", "html", "ol", "optgroup", "select", "table", "tbody", "tfoot",
+    "thead", "tr", "ul",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    // exactly the tree, nothing added or left out
+    Compact,
+    // one element per line, indented by depth
+    Pretty(usize),
+    // comments and redundant white space and quotes left out
+    Minified,
+}
+
+/// Writes nodes as html that parses back into the same tree. Attributes come
+/// out in the order they were set and text is escaped.
+///
+/// `Serializer::new()` writes the tree exactly, `.pretty(indent)` puts each
+/// element on a line of its own and `.minified()` leaves out comments and
+/// the white space that doesn't render.
+#[derive(Debug, Clone)]
+pub struct Serializer {
+    style: Style,
+}
+
+impl Default for Serializer {
+    fn default() -> Self {
+        Serializer::new()
+    }
+}
+
+impl Serializer {
+    pub fn new() -> Serializer {
+        Serializer { style: Style::Compact }
+    }
+
+    /// Indent by `indent` spaces per level. White space only text between
+    /// elements is replaced, text mixed with elements is left on one line.
+    pub fn pretty(mut self, indent: usize) -> Serializer {
+        self.style = Style::Pretty(indent);
+        self
+    }
+
+    pub fn minified(mut self) -> Serializer {
+        self.style = Style::Minified;
+        self
+    }
+
+    pub fn serialize(&self, node: &dom::Node) -> String {
+        let mut result = String::new();
+        self.write(node, &mut result).expect("writing to a String doesn't fail");
+        result
+    }
+
+    pub fn write<W: Write>(&self, node: &dom::Node, out: &mut W) -> fmt::Result {
+        match self.style {
+            Style::Pretty(indent) => self.write_pretty(node, indent, 0, out),
+            _ => self.write_node(node, None, false, out),
+        }
+    }
+
+    // `parent` is the element holding `node`, `preserve` whether white space
+    // is kept as it is there
+    fn write_node<W: Write>(&self, node: &dom::Node, parent: Option<&dom::ElementData>, preserve: bool, out: &mut W) -> fmt::Result {
+        match &node.node_type {
+            dom::NodeType::Text(text) => {
+                if parent.is_some_and(|parent| is_html(parent, LITERAL_TEXT_ELEMENTS)) {
+                    out.write_str(text)
+                } else if self.style == Style::Minified && !preserve {
+                    out.write_str(&escape(&collapse_spaces(text), false))
+                } else {
+                    out.write_str(&escape(text, false))
+                }
+            }
+            dom::NodeType::Element(elem) => {
+                self.write_start_tag(elem, node.children.is_empty(), out)?;
+                if is_void(elem) || (elem.is_foreign() && node.children.is_empty()) {
+                    return Ok(());
+                }
+                // the parser drops a newline right after these start tags
+                if is_html(elem, SKIPS_LEADING_NEWLINE) && starts_with_newline(&node.children) {
+                    out.write_char('\n')?;
+                }
+                self.write_children(node, Some(elem), preserve || is_html(elem, PRESERVES_SPACE), out)?;
+                write!(out, "</{}>", elem.tag_name)
+            }
+            dom::NodeType::Comment(_) if self.style == Style::Minified => Ok(()),
+            dom::NodeType::Comment(comment) => write!(out, "<!--{}-->", comment),
+            dom::NodeType::Doctype(doctype) => {
+                write!(out, "<!DOCTYPE {}", doctype.name)?;
+                match (&doctype.public_id, &doctype.system_id) {
+                    (Some(public_id), Some(system_id)) => write!(out, " PUBLIC \"{}\" \"{}\"", public_id, system_id)?,
+                    (Some(public_id), None) => write!(out, " PUBLIC \"{}\"", public_id)?,
+                    (None, Some(system_id)) => write!(out, " SYSTEM \"{}\"", system_id)?,
+                    (None, None) => {}
+                }
+                out.write_char('>')
+            }
+            dom::NodeType::ProcessingInstruction(target, data) => write!(out, "<?{} {}?>", target, data),
+            dom::NodeType::CData(data) => write!(out, "<![CDATA[{}]]>", data),
+            dom::NodeType::Document(_) => self.write_children(node, None, false, out),
+        }
+    }
+
+    fn write_children<W: Write>(&self, node: &dom::Node, elem: Option<&dom::ElementData>, preserve: bool, out: &mut W) -> fmt::Result {
+        // white space between the children of a document or of a table doesn't render
+        let drops_space = self.style == Style::Minified
+            && elem.is_none_or(|elem| is_html(elem, NO_TEXT_CONTENT));
+        for child in &node.children {
+            if !(drops_space && is_whitespace_text(child)) {
+                self.write_node(child, elem, preserve, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_start_tag<W: Write>(&self, elem: &dom::ElementData, empty: bool, out: &mut W) -> fmt::Result {
+        write!(out, "<{}", elem.tag_name)?;
+        let mut unquoted = false;
+        for (name, value) in &elem.attributes {
+            write!(out, " {}", name)?;
+            unquoted = self.style == Style::Minified && can_leave_unquoted(value);
+            if self.style == Style::Minified && value.is_empty() {
+                // `checked` reads the same as `checked=""`
+            } else if unquoted {
+                write!(out, "={}", escape(value, true))?;
+            } else {
+                write!(out, "=\"{}\"", escape(value, true))?;
+            }
+        }
+        if elem.is_foreign() && empty {
+            // svg and mathml elements may close themselves, html ones can't.
+            // the slash would end up in an unquoted value right before it
+            out.write_str(if unquoted { " />" } else { "/>" })
+        } else {
+            out.write_char('>')
+        }
+    }
+
+    fn write_pretty<W: Write>(&self, node: &dom::Node, indent: usize, depth: usize, out: &mut W) -> fmt::Result {
+        if is_whitespace_text(node) {
+            return Ok(());
+        }
+        write!(out, "{:width$}", "", width = indent * depth)?;
+        match &node.node_type {
+            dom::NodeType::Element(elem) if breaks_into_lines(node, elem) => {
+                self.write_start_tag(elem, false, out)?;
+                out.write_char('\n')?;
+                for child in &node.children {
+                    self.write_pretty(child, indent, depth + 1, out)?;
+                }
+                writeln!(out, "{:width$}</{}>", "", elem.tag_name, width = indent * depth)
+            }
+            dom::NodeType::Document(_) => {
+                for child in &node.children {
+                    self.write_pretty(child, indent, depth, out)?;
+                }
+                Ok(())
+            }
+            _ => {
+                self.write_node(node, None, false, out)?;
+                out.write_char('\n')
+            }
+        }
+    }
+}
+
+// whether an element gets its children on lines of their own when
+// pretty-printing, which adds white space to it. Those holding text or
+// keeping their white space stay on one line
+fn breaks_into_lines(node: &dom::Node, elem: &dom::ElementData) -> bool {
+    let has_text = node.children.iter().any(|child| match &child.node_type {
+        dom::NodeType::Text(_) => !is_whitespace_text(child),
+        dom::NodeType::CData(_) => true,
+        _ => false,
+    });
+    !has_text
+        && !is_void(elem)
+        && !is_html(elem, PRESERVES_SPACE)
+        && !is_html(elem, LITERAL_TEXT_ELEMENTS)
+        && node.children.iter().any(|child| !is_whitespace_text(child))
+}
+
+// whether `elem` is an html element named in `names`
+fn is_html(elem: &dom::ElementData, names: &[&str]) -> bool {
+    names.iter().any(|name| elem.is_html_element(name))
+}
+
+fn is_void(elem: &dom::ElementData) -> bool {
+    is_html(elem, VOID_ELEMENTS)
+}
+
+fn is_whitespace_text(node: &dom::Node) -> bool {
+    matches!(&node.node_type, dom::NodeType::Text(text) if text.chars().all(|c| c.is_ascii_whitespace()))
+}
+
+fn starts_with_newline(children: &[dom::Node]) -> bool {
+    matches!(children.first().map(|child| &child.node_type), Some(dom::NodeType::Text(text)) if text.starts_with('\n'))
+}
+
+// runs of white space as a single space
+fn collapse_spaces(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut after_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !after_space {
+                result.push(' ');
+            }
+            after_space = true;
+        } else {
+            result.push(c);
+            after_space = false;
+        }
+    }
+    result
+}
+
+// whether an attribute value reads back the same without quotes
+fn can_leave_unquoted(value: &str) -> bool {
+    !value.is_empty() && !value.chars().any(|c| c.is_ascii_whitespace() || "\"'=<>`".contains(c))
+}
+
+// escape text or an attribute value so that it reads back the same
+fn escape(s: &str, in_attribute: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{a0}' => result.push_str("&nbsp;"),
+            '"' if in_attribute => result.push_str("&quot;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Parser;
+
+    fn parse(source: &str) -> dom::Node {
+        Parser::new(source).tolerant().parse_document().unwrap()
+    }
+
+    const DOCUMENT: &str = "<!DOCTYPE html><html lang=en><head><meta charset=utf-8><title>a &amp; b</title>\n\
+        <style>p > a { color: red }</style></head>\n<body class='x  y' id=main data-z=\"'&quot;\">\n\
+        <!-- note --><p>one   <b>two</b>&nbsp;&lt;three&gt;<br><img src=a.png alt=\"\"></p>\n\
+        <pre>\n\nindented\n  code</pre><textarea>\nx</textarea>\n\
+        <ul>\n  <li>a</li>\n  <li>b</li>\n</ul><svg viewBox='0 0 1 1'><circle r=1 /><text>t</text></svg>\n\
+        <script>if (a < b && c) {}</script></body></html>";
+
+    #[test]
+    fn test_serialize() {
+        let html = parse("<p id=a class=b title='x\"y'>1 < 2 &amp; 3<br><!--c--></p>");
+        let body = &html.document_element().unwrap().children[1];
+        assert_eq!(
+            Serializer::new().serialize(body),
+            "<body><p id=\"a\" class=\"b\" title=\"x&quot;y\">1 &lt; 2 &amp; 3<br><!--c--></p></body>"
+        );
+
+        // attributes keep their order however many there are
+        let html = parse("<a z=1 y=2 x=3 w=4 v=5 u=6 t=7>");
+        let a = &html.document_element().unwrap().children[1].children[0];
+        assert_eq!(Serializer::new().serialize(a), "<a z=\"1\" y=\"2\" x=\"3\" w=\"4\" v=\"5\" u=\"6\" t=\"7\"></a>");
+
+        // raw text isn't escaped, and a leading newline survives the parser dropping one
+        let html = parse("<script>a<b</script><pre>\n\nx</pre>");
+        let output = Serializer::new().serialize(&html);
+        assert!(output.contains("<script>a<b</script>"));
+        assert!(output.contains("<pre>\n\nx</pre>"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for serializer in &[Serializer::new(), Serializer::new().pretty(2), Serializer::new().minified()] {
+            let once = serializer.serialize(&parse(DOCUMENT));
+            let twice = serializer.serialize(&parse(&once));
+            assert_eq!(once, twice);
+        }
+
+        // the exact output reads back to the same tree
+        let html = parse(DOCUMENT);
+        let output = Serializer::new().serialize(&html);
+        assert_eq!(format!("{:?}", strip_sources(parse(&output))), format!("{:?}", strip_sources(html)));
+    }
+
+    fn strip_sources(mut node: dom::Node) -> dom::Node {
+        node.source = None;
+        if let dom::NodeType::Element(elem) = &mut node.node_type {
+            elem.attribute_sources.clear();
+        }
+        node.children = node.children.into_iter().map(strip_sources).collect();
+        node
+    }
+
+    #[test]
+    fn test_pretty() {
+        let html = parse("<!DOCTYPE html><ul><li>a <b>b</b></li><li><p>c</p><p>d</p></li></ul>");
+        let expected = "<!DOCTYPE html>\n<html>\n  <head></head>\n  <body>\n    <ul>\n      <li>a <b>b</b></li>\n      <li>\n        <p>c</p>\n        <p>d</p>\n      </li>\n    </ul>\n  </body>\n</html>\n";
+        assert_eq!(Serializer::new().pretty(2).serialize(&html), expected);
+        assert_eq!(format!("{:#}", html), expected);
+    }
+
+    #[test]
+    fn test_minified() {
+        let html = parse("<ul>\n  <li class='a b' hidden=''>x  \n y</li>\n</ul><!-- gone --><pre> a  b </pre><svg><path d=M0 /></svg>");
+        let body = &html.document_element().unwrap().children[1];
+        assert_eq!(
+            Serializer::new().minified().serialize(body),
+            "<body><ul><li class=\"a b\" hidden>x y</li></ul><pre> a  b </pre><svg><path d=M0 /></svg></body>"
+        );
+    }
+}
@@ -0,0 +1,989 @@
+// Splitting HTML into tokens, which html::Parser builds the tree from.
+// Input can be given all at once, in chunks, or read from a `Read`.
+
+use super::{entities, ParseError, RAW_TEXT_ELEMENTS, RCDATA_ELEMENTS};
+use crate::dom;
+use std::io;
+use std::io::Read;
+
+/// An attribute of a tag as written, with where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: String,
+    pub source: dom::SourceRange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    // in source order, duplicates included
+    pub attributes: Vec<Attribute>,
+    // written as `<name/>`
+    pub self_closing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    StartTag(Tag),
+    // attributes of end tags are dropped, so they have none
+    EndTag(Tag),
+    Text(String),
+    Comment(String),
+    Doctype { doctype: dom::DoctypeData, force_quirks: bool },
+    CData(String),
+    // target and data, like <?xml-stylesheet href="a.css"?>
+    ProcessingInstruction(String, String),
+}
+
+// the input buffered so far ends in the middle of a token
+struct Incomplete;
+type Lex<T> = Result<T, Incomplete>;
+
+// where a doctype stops making sense, and why
+type DoctypeError = (usize, &'static str);
+
+const START_POSITION: dom::SourcePosition = dom::SourcePosition { offset: 0, line: 1, column: 1 };
+
+// how many consumed bytes to keep around before dropping them from the buffer
+const COMPACT_THRESHOLD: usize = 8 * 1024;
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+pub struct Tokenizer {
+    // the part of the input not dropped yet, starting at absolute offset `base`
+    input: String,
+    pos: usize,
+    base: usize,
+    // no more input will be fed
+    ended: bool,
+
+    // keep the case of tag and attribute names instead of lowercasing them
+    preserve_case: bool,
+    // inside svg or mathml, where CDATA sections exist and nothing is raw text
+    foreign: bool,
+    // the end tag that ends the raw text being read, None for text running to
+    // the end of input, and whether references are decoded
+    raw_text: Option<(Option<String>, bool)>,
+
+    // a place whose line and column are known, to locate later offsets from
+    mark: dom::SourcePosition,
+    last_source: Option<dom::SourceRange>,
+    errors: Vec<ParseError>,
+
+    // located once the current token turns out to be complete
+    pending_errors: Vec<(usize, String)>,
+    attribute_offsets: Vec<(usize, usize)>,
+}
+
+impl Tokenizer {
+    /// A tokenizer over the whole input.
+    pub fn new<S: ToString>(source: S) -> Tokenizer {
+        let mut tokenizer = Tokenizer::streaming();
+        tokenizer.feed(&source.to_string());
+        tokenizer.end();
+        tokenizer
+    }
+
+    /// A tokenizer waiting for input through `feed`, until `end` is called.
+    pub fn streaming() -> Tokenizer {
+        Tokenizer {
+            input: String::new(),
+            pos: 0,
+            base: 0,
+            ended: false,
+            preserve_case: false,
+            foreign: false,
+            raw_text: None,
+            mark: START_POSITION,
+            last_source: None,
+            errors: Vec::new(),
+            pending_errors: Vec::new(),
+            attribute_offsets: Vec::new(),
+        }
+    }
+
+    /// Tokens read from `reader` in chunks, decoded as UTF-8.
+    pub fn from_reader<R: Read>(reader: R) -> ReaderTokens<R> {
+        ReaderTokens {
+            reader,
+            tokenizer: Tokenizer::streaming(),
+            bytes: Vec::new(),
+            done: false,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &str) {
+        self.input.push_str(chunk);
+    }
+
+    /// No more input will come: whatever is buffered makes the last tokens.
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
+    /// Keep tag and attribute names as written, like XML, instead of lowercasing them.
+    pub fn set_preserve_case(&mut self, preserve_case: bool) {
+        self.preserve_case = preserve_case;
+    }
+
+    /// Tell the tokenizer it is inside svg or mathml, where `<![CDATA[` starts
+    /// a CDATA section and `<title>` or `<style>` hold markup.
+    pub fn set_foreign_content(&mut self, foreign: bool) {
+        self.foreign = foreign;
+    }
+
+    /// Read all the input left as one text token, the way the content of a
+    /// <script> (or with references decoded, a <textarea>) given on its own is read.
+    pub fn set_text_only(&mut self, decode_references: bool) {
+        self.raw_text = Some((None, decode_references));
+    }
+
+    /// Problems met so far, in source order. Tokens are still produced for
+    /// malformed input the way HTML5 browsers recover from it.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Where the last token returned by `next_token` was found.
+    pub fn last_source(&self) -> Option<dom::SourceRange> {
+        self.last_source
+    }
+
+    /// The position just after the last token.
+    pub fn position(&self) -> dom::SourcePosition {
+        self.mark
+    }
+
+    /// The next token, or None when the input fed so far has run out.
+    pub fn next_token(&mut self) -> Option<Token> {
+        loop {
+            if self.raw_text.is_none() && self.eof() {
+                return None;
+            }
+            let start = self.pos;
+            self.pending_errors.clear();
+            self.attribute_offsets.clear();
+            match self.lex_token() {
+                Ok(Some(token)) => {
+                    let token = self.finish_token(start, token);
+                    self.compact();
+                    return Some(token);
+                }
+                // something that makes no token, like `</>` or an empty <style>
+                Ok(None) => {
+                    self.finish_token(start, Token::Text(String::new()));
+                }
+                Err(Incomplete) => {
+                    self.pos = start;
+                    return None;
+                }
+            }
+        }
+    }
+
+    // locate the token, its attributes and its errors now that it is complete
+    fn finish_token(&mut self, start: usize, mut token: Token) -> Token {
+        let mut errors = std::mem::take(&mut self.pending_errors);
+        let attribute_offsets = std::mem::take(&mut self.attribute_offsets);
+        errors.sort_by_key(|(offset, _)| *offset);
+
+        // lines are counted in one pass over the token, so every offset is
+        // located in ascending order
+        let mut offsets = vec![start, self.pos];
+        offsets.extend(errors.iter().map(|(offset, _)| *offset));
+        offsets.extend(attribute_offsets.iter().flat_map(|(start, end)| vec![*start, *end]));
+        offsets.sort_unstable();
+        offsets.dedup();
+        let positions: Vec<dom::SourcePosition> = offsets.iter()
+            .map(|offset| self.locate(self.base + offset))
+            .collect();
+        let at = |offset: usize| positions[offsets.binary_search(&offset).unwrap()];
+
+        for (offset, message) in errors {
+            let position = at(offset);
+            self.errors.push(ParseError {
+                offset: position.offset,
+                line: position.line,
+                column: position.column,
+                message,
+            });
+        }
+        if let Token::StartTag(tag) | Token::EndTag(tag) = &mut token {
+            for (attr, (start, end)) in tag.attributes.iter_mut().zip(attribute_offsets) {
+                attr.source = dom::SourceRange { start: at(start), end: at(end) };
+            }
+        }
+        self.last_source = Some(dom::SourceRange { start: at(start), end: at(self.pos) });
+        token
+    }
+
+    // the line and column of an absolute offset, which must not be before the
+    // last one located
+    fn locate(&mut self, offset: usize) -> dom::SourcePosition {
+        let from = self.mark.offset - self.base;
+        let to = offset - self.base;
+        self.mark = advance(self.mark, &self.input[from..to]);
+        self.mark
+    }
+
+    // drop input that no token will look at again
+    fn compact(&mut self) {
+        let consumed = self.mark.offset - self.base;
+        if consumed > COMPACT_THRESHOLD && consumed * 2 > self.input.len() {
+            self.input.drain(..consumed);
+            self.base += consumed;
+            self.pos -= consumed;
+        }
+    }
+
+    fn error(&mut self, offset: usize, message: &str) {
+        self.pending_errors.push((offset, message.to_string()));
+    }
+
+    // -------- reading the buffer --------
+
+    fn eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+    fn next_char(&self) -> char {
+        self.input[self.pos..].chars().next().unwrap()
+    }
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s)
+    }
+    // whether the input starts with `s`, which can only be told once enough is fed
+    fn peek_is(&self, s: &str, ignore_case: bool) -> Lex<bool> {
+        let rest = &self.input[self.pos..];
+        let n = rest.len().min(s.len());
+        let matches = match rest.get(..n) {
+            Some(prefix) if ignore_case => prefix.eq_ignore_ascii_case(&s[..n]),
+            Some(prefix) => prefix == &s[..n],
+            None => false,
+        };
+        if matches && n < s.len() && !self.ended {
+            Err(Incomplete)
+        } else {
+            Ok(matches && n == s.len())
+        }
+    }
+    // fail unless all input is in, for when a token runs to the end of the buffer
+    fn need_more(&self) -> Lex<()> {
+        if self.ended {
+            Ok(())
+        } else {
+            Err(Incomplete)
+        }
+    }
+    fn consume_char(&mut self) -> char {
+        let mut iter = self.input[self.pos..].char_indices();
+        let (_, cur_char) = iter.next().unwrap();
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
+        self.pos += next_pos;
+        cur_char
+    }
+    fn consume_while<F>(&mut self, test: F) -> String
+            where F: Fn(char) -> bool {
+        let mut result = String::new();
+        while !self.eof() && test(self.next_char()) {
+            result.push(self.consume_char());
+        }
+        result
+    }
+    // consume up to `s` or the end of input, `s` itself is left
+    fn consume_until(&mut self, s: &str) -> Lex<String> {
+        match self.input[self.pos..].find(s) {
+            Some(i) => {
+                let result = self.input[self.pos..self.pos + i].to_string();
+                self.pos += i;
+                Ok(result)
+            }
+            None => {
+                self.need_more()?;
+                let result = self.input[self.pos..].to_string();
+                self.pos = self.input.len();
+                Ok(result)
+            }
+        }
+    }
+    fn consume_whitespace(&mut self) {
+        self.consume_while(char::is_whitespace);
+    }
+
+    fn parse_tag_name(&mut self) -> String {
+        self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-'))
+    }
+    fn adjust_case(&self, name: String) -> String {
+        if self.preserve_case {
+            name
+        } else {
+            name.to_ascii_lowercase()
+        }
+    }
+
+    // -------- tokens --------
+
+    fn lex_token(&mut self) -> Lex<Option<Token>> {
+        if let Some((end_tag, decode)) = self.raw_text.clone() {
+            return self.lex_raw_text(end_tag.as_deref(), decode);
+        }
+        if self.next_char() != '<' || !self.at_markup()? {
+            return self.lex_text().map(Some);
+        }
+
+        if self.peek_is("<!--", false)? {
+            self.lex_comment().map(Some)
+        } else if self.peek_is("<!DOCTYPE", true)? {
+            self.lex_doctype().map(Some)
+        } else if self.foreign && self.peek_is("<![CDATA[", false)? {
+            self.lex_cdata().map(Some)
+        } else if self.starts_with("<!") {
+            self.lex_bogus_comment("unknown markup declaration").map(Some)
+        } else if self.starts_with("<?") {
+            self.lex_processing_instruction().map(Some)
+        } else if self.starts_with("</") {
+            self.lex_end_tag()
+        } else {
+            self.lex_start_tag()
+        }
+    }
+
+    // whether the `<` at the current position starts markup rather than text
+    fn at_markup(&self) -> Lex<bool> {
+        let mut chars = self.input[self.pos..].chars().skip(1);
+        let markup = match chars.next() {
+            Some('!') | Some('?') => true,
+            // `</` at the very end is text, anything else makes a tag or a bogus comment
+            Some('/') => match chars.next() {
+                Some(_) => true,
+                None if self.ended => false,
+                None => return Err(Incomplete),
+            },
+            Some(c) => c.is_ascii_alphabetic(),
+            None if self.ended => false,
+            None => return Err(Incomplete),
+        };
+        Ok(markup)
+    }
+
+    // text up to the next markup, a `<` that starts no tag is text too
+    fn lex_text(&mut self) -> Lex<Token> {
+        let start = self.pos;
+        loop {
+            self.consume_while(|c| c != '<');
+            if self.eof() {
+                self.need_more()?;
+                break;
+            }
+            if self.at_markup()? {
+                break;
+            }
+            self.consume_char();
+            if !self.starts_with("/") {
+                self.error(self.pos, "expected a tag name after `<`");
+            }
+        }
+        let text = &self.input[start..self.pos];
+        Ok(Token::Text(entities::decode(text, false)))
+    }
+
+    // the content of a raw text or rcdata element: everything up to its end
+    // tag, like `</script>`, `</SCRIPT >` or `</script/>`
+    fn lex_raw_text(&mut self, end_tag: Option<&str>, decode: bool) -> Lex<Option<Token>> {
+        let rest = &self.input[self.pos..];
+        let mut end = None;
+        let mut from = 0;
+        while let (Some(end_tag), Some(i)) = (end_tag, rest[from..].find("</")) {
+            let candidate = &rest[from + i + 2..];
+            if candidate.len() <= end_tag.len() && !self.ended {
+                return Err(Incomplete);
+            }
+            let closes = candidate.len() >= end_tag.len()
+                && candidate.is_char_boundary(end_tag.len())
+                && candidate[..end_tag.len()].eq_ignore_ascii_case(end_tag)
+                && candidate[end_tag.len()..].chars().next()
+                    .is_none_or(|c| c == '>' || c == '/' || c.is_whitespace());
+            if closes {
+                end = Some(from + i);
+                break;
+            }
+            from += i + 2;
+        }
+        let end = match end {
+            Some(end) => end,
+            None => {
+                self.need_more()?;
+                rest.len()
+            }
+        };
+
+        let text = &rest[..end];
+        let text = if decode { entities::decode(text, false) } else { text.to_string() };
+        self.pos += end;
+        self.raw_text = None;
+        if text.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Token::Text(text)))
+        }
+    }
+
+    fn lex_comment(&mut self) -> Lex<Token> {
+        let start = self.pos;
+        self.pos += "<!--".len();
+        let comment = self.consume_until("-->")?;
+        if self.eof() {
+            self.error(start, "unterminated comment");
+        } else {
+            self.pos += "-->".len();
+        }
+        Ok(Token::Comment(comment))
+    }
+
+    // markup like `<!ELEMENT br EMPTY>`, `<?php ... ?>` or `</ p>` that html
+    // turns into a comment running to the next `>`
+    fn lex_bogus_comment(&mut self, message: &str) -> Lex<Token> {
+        let start = self.pos;
+        self.consume_char();
+        if self.starts_with("!") || self.starts_with("/") {
+            self.consume_char();
+        }
+        let comment = self.consume_until(">")?;
+        if !self.eof() {
+            self.consume_char();
+        }
+        self.error(start, message);
+        Ok(Token::Comment(comment))
+    }
+
+    // <![CDATA[ ... ]]>, only found in svg and mathml
+    fn lex_cdata(&mut self) -> Lex<Token> {
+        let start = self.pos;
+        self.pos += "<![CDATA[".len();
+        let data = self.consume_until("]]>")?;
+        if self.eof() {
+            self.error(start, "unterminated CDATA section");
+        } else {
+            self.pos += "]]>".len();
+        }
+        Ok(Token::CData(data))
+    }
+
+    // <?target data?>
+    fn lex_processing_instruction(&mut self) -> Lex<Token> {
+        let start = self.pos;
+        self.pos += "<?".len();
+        let target = self.parse_tag_name();
+        if target.is_empty() {
+            self.pos = start;
+            return self.lex_bogus_comment("expected a processing instruction target");
+        }
+        self.consume_whitespace();
+        let data = self.consume_until("?>")?;
+        if self.eof() {
+            self.error(start, "unterminated processing instruction");
+        } else {
+            self.pos += "?>".len();
+        }
+        Ok(Token::ProcessingInstruction(target, data))
+    }
+
+    // <!DOCTYPE html>, <!DOCTYPE html SYSTEM "about:legacy-compat"> or
+    // <!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN" "http://www.w3.org/TR/html4/strict.dtd">
+    fn lex_doctype(&mut self) -> Lex<Token> {
+        // the whole doctype must be in before it can be read
+        if !self.input[self.pos..].contains('>') {
+            self.need_more()?;
+        }
+        self.pos += "<!DOCTYPE".len();
+        self.consume_whitespace();
+        let name = self.consume_while(|c| !c.is_whitespace() && c != '>').to_ascii_lowercase();
+
+        let mut force_quirks = name.is_empty();
+        let (public_id, system_id) = match self.lex_doctype_ids() {
+            Ok(ids) => ids,
+            Err((offset, message)) => {
+                // whatever the doctype says, it can't be trusted
+                self.error(offset, message);
+                force_quirks = true;
+                self.consume_while(|c| c != '>');
+                if !self.eof() {
+                    self.consume_char();
+                }
+                (None, None)
+            }
+        };
+        Ok(Token::Doctype {
+            doctype: dom::DoctypeData { name, public_id, system_id },
+            force_quirks,
+        })
+    }
+    fn lex_doctype_ids(&mut self) -> Result<(Option<String>, Option<String>), DoctypeError> {
+        let mut public_id = None;
+        let mut system_id = None;
+        self.consume_whitespace();
+        if self.input[self.pos..].get(..6).is_some_and(|s| s.eq_ignore_ascii_case("PUBLIC")) {
+            self.pos += "PUBLIC".len();
+            self.consume_whitespace();
+            public_id = Some(self.lex_doctype_id()?);
+            self.consume_whitespace();
+            if !self.starts_with(">") {
+                system_id = Some(self.lex_doctype_id()?);
+            }
+        } else if self.input[self.pos..].get(..6).is_some_and(|s| s.eq_ignore_ascii_case("SYSTEM")) {
+            self.pos += "SYSTEM".len();
+            self.consume_whitespace();
+            system_id = Some(self.lex_doctype_id()?);
+        }
+        self.consume_whitespace();
+        if self.eof() {
+            return Err((self.pos, "expected `>` but reached end of input"));
+        }
+        if !self.starts_with(">") {
+            return Err((self.pos, "unexpected text in doctype"));
+        }
+        self.consume_char();
+        Ok((public_id, system_id))
+    }
+    fn lex_doctype_id(&mut self) -> Result<String, DoctypeError> {
+        let start = self.pos;
+        if self.eof() || (self.next_char() != '"' && self.next_char() != '\'') {
+            return Err((start, "expected a quoted doctype identifier"));
+        }
+        let open_quote = self.consume_char();
+        let id = self.consume_while(|c| c != open_quote && c != '>');
+        if self.eof() || self.next_char() != open_quote {
+            return Err((start, "unterminated doctype identifier"));
+        }
+        self.consume_char();
+        Ok(id)
+    }
+
+    fn lex_start_tag(&mut self) -> Lex<Option<Token>> {
+        self.consume_char();
+        let name = self.parse_tag_name();
+        let name = self.adjust_case(name);
+        let tag = match self.lex_tag_rest(name)? {
+            Some(tag) => tag,
+            None => return Ok(None),
+        };
+
+        let lowercase_name = tag.name.to_ascii_lowercase();
+        if !self.foreign && RAW_TEXT_ELEMENTS.contains(&lowercase_name.as_str()) {
+            self.raw_text = Some((Some(lowercase_name), false));
+        } else if !self.foreign && RCDATA_ELEMENTS.contains(&lowercase_name.as_str()) {
+            self.raw_text = Some((Some(lowercase_name), true));
+        }
+        Ok(Some(Token::StartTag(tag)))
+    }
+
+    fn lex_end_tag(&mut self) -> Lex<Option<Token>> {
+        let start = self.pos;
+        self.pos += "</".len();
+        if self.starts_with(">") {
+            self.consume_char();
+            self.error(start, "empty end tag");
+            return Ok(None);
+        }
+        if !self.next_char().is_ascii_alphabetic() {
+            self.pos = start;
+            return self.lex_bogus_comment("invalid end tag").map(Some);
+        }
+        let name = self.parse_tag_name();
+        let name = self.adjust_case(name);
+        Ok(self.lex_tag_rest(name)?.map(|mut tag| {
+            if !tag.attributes.is_empty() {
+                self.error(start, "attributes on an end tag");
+                tag.attributes.clear();
+                self.attribute_offsets.clear();
+            }
+            Token::EndTag(tag)
+        }))
+    }
+
+    // the attributes and the closing `>` or `/>` of a tag; None for a tag cut
+    // off by the end of input, which is dropped
+    fn lex_tag_rest(&mut self, name: String) -> Lex<Option<Tag>> {
+        let mut attributes = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                self.need_more()?;
+                self.error(self.pos, "unexpected end of input inside a tag");
+                return Ok(None);
+            }
+            if self.next_char() == '>' {
+                self.consume_char();
+                return Ok(Some(Tag { name, attributes, self_closing: false }));
+            }
+            if self.peek_is("/>", false)? {
+                self.pos += "/>".len();
+                return Ok(Some(Tag { name, attributes, self_closing: true }));
+            }
+            if self.next_char() == '/' {
+                // a slash that doesn't close the tag is ignored
+                self.consume_char();
+                continue;
+            }
+            let start = self.pos;
+            match self.lex_attr()? {
+                Some((name, value)) => {
+                    self.attribute_offsets.push((start, self.pos));
+                    attributes.push(Attribute { name, value, source: dom::SourceRange { start: START_POSITION, end: START_POSITION } });
+                }
+                None => {
+                    // skip the offending character, like a stray `"`
+                    self.error(start, "expected an attribute name");
+                    self.consume_char();
+                }
+            }
+        }
+    }
+
+    // an attribute like `name="value"`, `name='value'`, `name=value` or just
+    // `name`, which is a boolean attribute with an empty value. Names are
+    // anything up to whitespace, `=`, `/` or `>`, like `data-x`, `xml:lang`,
+    // `v-on:click` or `ng.model`
+    fn lex_attr(&mut self) -> Lex<Option<(String, String)>> {
+        let name = self.consume_while(|c| !c.is_whitespace() && !"/>=\"'<".contains(c));
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let name = self.adjust_case(name);
+        self.consume_whitespace();
+        if self.eof() {
+            self.need_more()?;
+        }
+
+        if !self.starts_with("=") {
+            return Ok(Some((name, String::new())));
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let value = self.lex_attr_value()?;
+        Ok(Some((name, value)))
+    }
+    fn lex_attr_value(&mut self) -> Lex<String> {
+        let start = self.pos;
+        if self.eof() {
+            self.need_more()?;
+            return Ok(String::new());
+        }
+        if self.next_char() == '>' {
+            self.error(start, "missing attribute value");
+            return Ok(String::new());
+        }
+        if self.next_char() != '"' && self.next_char() != '\'' {
+            let value = self.consume_while(|c| !c.is_whitespace() && c != '>');
+            if self.eof() {
+                self.need_more()?;
+            }
+            return Ok(entities::decode(&value, true));
+        }
+        let open_quote = self.consume_char();
+        let value = self.consume_while(|c| c != open_quote);
+        if self.eof() {
+            self.need_more()?;
+            self.error(start, "unterminated attribute value");
+            return Ok(String::new());
+        }
+        self.consume_char();
+        Ok(entities::decode(&value, true))
+    }
+}
+
+impl Iterator for Tokenizer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_token()
+    }
+}
+
+/// The position reached after reading `text` from `position`.
+pub(super) fn advance(mut position: dom::SourcePosition, text: &str) -> dom::SourcePosition {
+    for c in text.chars() {
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    }
+    position.offset += text.len();
+    position
+}
+
+/// Tokens read from a `Read` a chunk at a time, see `Tokenizer::from_reader`.
+/// Bytes that aren't UTF-8 are replaced with U+FFFD.
+pub struct ReaderTokens<R> {
+    reader: R,
+    tokenizer: Tokenizer,
+    // the start of a char cut off at the end of the last chunk
+    bytes: Vec<u8>,
+    done: bool,
+}
+
+impl<R> ReaderTokens<R> {
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    pub fn tokenizer_mut(&mut self) -> &mut Tokenizer {
+        &mut self.tokenizer
+    }
+}
+
+impl<R: Read> ReaderTokens<R> {
+    // decode the complete chars in `bytes` into the tokenizer
+    fn decode_bytes(&mut self) {
+        let mut rest = &self.bytes[..];
+        let mut text = String::new();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push('\u{FFFD}');
+                            rest = &after[len..];
+                        }
+                        None if self.done => {
+                            text.push('\u{FFFD}');
+                            rest = &[];
+                            break;
+                        }
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.bytes = rest.to_vec();
+        self.tokenizer.feed(&text);
+    }
+}
+
+impl<R: Read> Iterator for ReaderTokens<R> {
+    type Item = io::Result<Token>;
+
+    fn next(&mut self) -> Option<io::Result<Token>> {
+        loop {
+            if let Some(token) = self.tokenizer.next_token() {
+                return Some(Ok(token));
+            }
+            if self.done {
+                return None;
+            }
+            let mut chunk = [0; READ_CHUNK_SIZE];
+            match self.reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    self.decode_bytes();
+                    self.tokenizer.end();
+                }
+                Ok(n) => {
+                    self.bytes.extend_from_slice(&chunk[..n]);
+                    self.decode_bytes();
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, attributes: &[(&str, &str)], self_closing: bool) -> Tag {
+        Tag {
+            name: name.to_owned(),
+            attributes: attributes.iter().map(|(name, value)| Attribute {
+                name: name.to_string(),
+                value: value.to_string(),
+                source: dom::SourceRange { start: START_POSITION, end: START_POSITION },
+            }).collect(),
+            self_closing,
+        }
+    }
+
+    // tokens with the attribute sources blanked out, for comparing
+    fn tokens<I: Iterator<Item = Token>>(tokens: I) -> Vec<Token> {
+        tokens.map(|mut token| {
+            if let Token::StartTag(tag) | Token::EndTag(tag) = &mut token {
+                for attr in &mut tag.attributes {
+                    attr.source = dom::SourceRange { start: START_POSITION, end: START_POSITION };
+                }
+            }
+            token
+        }).collect()
+    }
+
+    #[test]
+    fn test_basic() {
+        let mut tokenizer = Tokenizer::new(String::from("Hello, Parser"));
+        assert_eq!(tokenizer.next_char(), 'H');
+        assert_eq!(tokenizer.pos, 0);
+
+        assert!(!tokenizer.starts_with("Hell "));
+        assert!(tokenizer.starts_with("Hell"));
+
+        let mut tmp = String::new();
+        while !tokenizer.eof() {
+            tmp.push(tokenizer.consume_char());
+        }
+        assert_eq!(tokenizer.pos, tmp.len());
+        assert_eq!(tokenizer.input, tmp);
+    }
+
+    #[test]
+    fn test_parse_tag_name() {
+        let mut tokenizer = Tokenizer::new("<html_body>");
+
+        tokenizer.parse_tag_name();
+        assert_eq!(tokenizer.pos, 0);
+
+        assert_eq!(tokenizer.consume_char(), '<');
+        let tag_name = tokenizer.parse_tag_name();
+        assert_eq!(tag_name, "html");
+    }
+
+    #[test]
+    fn test_tokens() {
+        let source = "<!DOCTYPE html><HTML lang=en><!-- c --><p class='a' hidden>x &amp; y</p><br/></html>";
+        let expected = vec![
+            Token::Doctype { doctype: dom::DoctypeData { name: "html".to_owned(), public_id: None, system_id: None }, force_quirks: false },
+            Token::StartTag(tag("html", &[("lang", "en")], false)),
+            Token::Comment(" c ".to_owned()),
+            Token::StartTag(tag("p", &[("class", "a"), ("hidden", "")], false)),
+            Token::Text("x & y".to_owned()),
+            Token::EndTag(tag("p", &[], false)),
+            Token::StartTag(tag("br", &[], true)),
+            Token::EndTag(tag("html", &[], false)),
+        ];
+        assert_eq!(tokens(Tokenizer::new(source)), expected);
+
+        let mut tokenizer = Tokenizer::new("<Svg viewBox='0 0 1 1'>");
+        tokenizer.set_preserve_case(true);
+        assert_eq!(tokens(tokenizer), vec![Token::StartTag(tag("Svg", &[("viewBox", "0 0 1 1")], false))]);
+    }
+
+    #[test]
+    fn test_raw_text_and_markup_in_text() {
+        let source = "<script>if (a < b) { x = '</p>'; }</script><title>a &lt; <b></title>1 < 2</>";
+        let expected = vec![
+            Token::StartTag(tag("script", &[], false)),
+            Token::Text("if (a < b) { x = '</p>'; }".to_owned()),
+            Token::EndTag(tag("script", &[], false)),
+            Token::StartTag(tag("title", &[], false)),
+            Token::Text("a < <b>".to_owned()),
+            Token::EndTag(tag("title", &[], false)),
+            Token::Text("1 < 2".to_owned()),
+        ];
+        let mut tokenizer = Tokenizer::new(source);
+        assert_eq!(tokens(&mut tokenizer), expected);
+        assert_eq!(tokenizer.errors().len(), 2);
+        assert_eq!(tokenizer.errors()[0].message, "expected a tag name after `<`");
+        assert_eq!(tokenizer.errors()[1].message, "empty end tag");
+
+        // in svg there is no raw text, but there are CDATA sections
+        let mut tokenizer = Tokenizer::new("<style><![CDATA[a<b]]></style>");
+        tokenizer.set_foreign_content(true);
+        assert_eq!(tokens(tokenizer), vec![
+            Token::StartTag(tag("style", &[], false)),
+            Token::CData("a<b".to_owned()),
+            Token::EndTag(tag("style", &[], false)),
+        ]);
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut tokenizer = Tokenizer::new("<a\n  href='x'>\nlink</a>");
+        let token = tokenizer.next_token().unwrap();
+        let source = tokenizer.last_source().unwrap();
+        assert_eq!((source.start.offset, source.end.offset), (0, 14));
+        assert_eq!((source.end.line, source.end.column), (2, 12));
+        match token {
+            Token::StartTag(tag) => {
+                let href = tag.attributes[0].source;
+                assert_eq!((href.start.line, href.start.column, href.end.column), (2, 3, 11));
+            }
+            _ => panic!("expected a start tag"),
+        }
+        tokenizer.next_token();
+        assert_eq!(tokenizer.last_source().unwrap().start.line, 2);
+        tokenizer.next_token();
+        assert_eq!(tokenizer.last_source().unwrap().start.column, 5);
+        assert_eq!(tokenizer.next_token(), None);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let source = "<!DOCTYPE html><html lang=\"en\"><!-- a comment --><p title='é &amp; ü'>héllo &amp; wörld<br/>\
+                      <script>if (a < b) {}</script><textarea>x&lt;y</textarea></p><?xml v?></html>";
+        let whole = tokens(Tokenizer::new(source));
+
+        // fed one char at a time, tokens come out the same
+        let mut tokenizer = Tokenizer::streaming();
+        let mut streamed = Vec::new();
+        for c in source.chars() {
+            tokenizer.feed(&c.to_string());
+            streamed.extend(tokens(&mut tokenizer));
+        }
+        tokenizer.end();
+        streamed.extend(tokens(&mut tokenizer));
+        assert_eq!(streamed, whole);
+
+        // as well as read in chunks that split chars in half
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(3).min(buf.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let read: Vec<Token> = Tokenizer::from_reader(Trickle(source.as_bytes())).map(Result::unwrap).collect();
+        assert_eq!(tokens(read.into_iter()), whole);
+
+        let read: Vec<Token> = Tokenizer::from_reader(&b"<p>\xFFok\xE2</p>"[..]).map(Result::unwrap).collect();
+        assert_eq!(read[1], Token::Text("\u{FFFD}ok\u{FFFD}".to_owned()));
+    }
+
+    #[test]
+    fn test_long_input() {
+        // the buffer is compacted as tokens are read, positions stay absolute
+        let item = "<li class=item>entry &amp; more</li>\n";
+        let source = item.repeat(2000);
+        let mut tokenizer = Tokenizer::streaming();
+        let mut count = 0;
+        for chunk in source.as_bytes().chunks(1000) {
+            tokenizer.feed(std::str::from_utf8(chunk).unwrap());
+            while tokenizer.next_token().is_some() {
+                count += 1;
+            }
+        }
+        tokenizer.end();
+        while tokenizer.next_token().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 2000 * 4);
+        assert!(tokenizer.input.len() < source.len());
+        let last = tokenizer.last_source().unwrap();
+        assert_eq!(last.end.offset, source.len());
+        assert_eq!((last.start.line, last.start.column), (2000, 37));
+    }
+}
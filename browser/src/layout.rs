@@ -1,6 +1,7 @@
 #![allow(unused)]
-use crate::style::StyledNode;
+use crate::style::{StyledNode, WhiteSpace};
 use crate::css;
+use crate::dom;
 use crate::css::{Value,Unit};
 
 #[derive(Default, Clone, Copy)]
@@ -55,14 +56,17 @@ struct LayoutBox<'a> {
     dimensions: Dimensions,
     box_type: BoxType<'a>,
     children: Vec<LayoutBox<'a>>,
+    // for text, what is left of it once its white space is processed
+    text: Option<String>,
 }
 
 impl<'a> LayoutBox<'a> {
     fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
-            box_type: box_type,
+            box_type,
             dimensions: Default::default(),
             children: Vec::new(),
+            text: None,
         }
     }
 
@@ -101,13 +105,13 @@ pub enum Display {
 
 impl StyledNode<'_> {
     fn value(&self, name: &str) -> Option<css::Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+        self.specified_values.get(name).cloned()
     }
     fn display(&self) -> Display {
         match self.value("display") {
             Some(css::Value::Keyword(s)) => match &s[..]{
                 "block" => Display::Block,
-                "none" => Display::Inline,
+                "none" => Display::None,
                 _ => Display::Inline
             }
             _ => Display::Inline
@@ -116,26 +120,199 @@ impl StyledNode<'_> {
     fn lookup(&self, name: &str, name1: &str, default: &css::Value) -> css::Value {
         self.value(name).unwrap_or_else(|| self.value(name1).unwrap_or_else(|| default.clone()))
     }
+    fn raw_text(&self, result: &mut String) {
+        if let dom::NodeType::Text(text) = self.node_type {
+            result.push_str(text);
+        }
+        for child in &self.children {
+            child.raw_text(result);
+        }
+    }
+    fn is_element(&self, tag_name: &str) -> bool {
+        matches!(self.node_type, dom::NodeType::Element(elem) if elem.is_html_element(tag_name))
+    }
+
+    /// The text of the node the way it is laid out, like innerText: nothing
+    /// from what isn't shown, white space processed as `white-space` says,
+    /// blocks on lines of their own and paragraphs apart by a blank line.
+    /// https://html.spec.whatwg.org/multipage/dom.html#the-innertext-idl-attribute
+    pub fn inner_text(&self) -> String {
+        if matches!(self.display(), Display::None) {
+            // an element that isn't shown gives its text as it is
+            let mut result = String::new();
+            self.raw_text(&mut result);
+            return result;
+        }
+        let layout = build_layout_tree(self);
+        let mut items = Vec::new();
+        text_items(&layout, &mut items);
+
+        let mut result = String::new();
+        // line breaks only count between pieces of text, the most asked for wins
+        let mut breaks = 0;
+        for item in items {
+            match item {
+                TextItem::Breaks(count) => breaks = breaks.max(count),
+                TextItem::Text(text) if text.is_empty() => {}
+                TextItem::Text(text) => {
+                    if !result.is_empty() {
+                        result.extend(std::iter::repeat_n('\n', breaks));
+                    }
+                    breaks = 0;
+                    result.push_str(&text);
+                }
+            }
+        }
+        result
+    }
+}
+
+enum TextItem {
+    Text(String),
+    // line breaks needed here unless there are as many already
+    Breaks(usize),
+}
+
+fn text_items(layout_box: &LayoutBox, items: &mut Vec<TextItem>) {
+    let breaks = match layout_box.box_type {
+        BoxType::BlockNode(node) if node.is_element("p") => 2,
+        BoxType::BlockNode(_) => 1,
+        _ => 0,
+    };
+    items.push(TextItem::Breaks(breaks));
+    items.extend(layout_box.text.clone().map(TextItem::Text));
+    if let BoxType::InlineNode(node) = layout_box.box_type {
+        if node.is_element("br") {
+            items.push(TextItem::Text("\n".to_string()));
+        }
+    }
+    for child in &layout_box.children {
+        text_items(child, items);
+    }
+    items.push(TextItem::Breaks(breaks));
 }
 
 fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    // nothing comes before the root, so its leading spaces are dropped
+    let mut after_space = true;
+    build_box(style_node, &mut after_space)
+}
+
+// `after_space` is whether the inline content so far ends in a collapsible
+// space, which the spaces after it collapse into, even across elements
+fn build_box<'a>(style_node: &'a StyledNode<'a>, after_space: &mut bool) -> LayoutBox<'a> {
     let mut root = LayoutBox::new(match style_node.display() {
         Display::Block => BoxType::BlockNode(style_node),
         Display::Inline => BoxType::InlineNode(style_node),
         Display::None => BoxType::AnonymousBlock,
     });
 
+    if let dom::NodeType::Text(text) = style_node.node_type {
+        let white_space = style_node.white_space();
+        let text = collapse_white_space(text, white_space, *after_space);
+        if let Some(last) = text.chars().last() {
+            *after_space = white_space.collapses_spaces() && (last == ' ' || last == '\n');
+        }
+        root.text = Some(text);
+        return root;
+    }
+
     for child in &style_node.children {
-        match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root.children.push(build_layout_tree(child)),
-            Display::None => root.children.push(build_layout_tree(child)),
+        // nothing is laid out for an element that isn't shown, nor under it
+        if matches!(child.display(), Display::None) {
+            continue;
+        }
+        // a block starts on a new line and ends its own
+        let is_block = matches!(child.display(), Display::Block);
+        if is_block {
+            trim_trailing_space(&mut root.children);
+            *after_space = true;
+        }
+        let child_box = build_box(child, after_space);
+        // so does a line break
+        if is_block || child.is_element("br") {
+            if !is_block {
+                trim_trailing_space(&mut root.children);
+            }
+            *after_space = true;
         }
+        // text that was all collapsible white space makes no box
+        if child_box.text.as_deref() == Some("") {
+            continue;
+        }
+        root.children.push(child_box);
+    }
+    if let BoxType::BlockNode(_) = root.box_type {
+        trim_trailing_space(&mut root.children);
+        *after_space = true;
     }
 
     root
 }
 
+fn is_white_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C')
+}
+
+// the first phase of white space processing, before text is broken into lines
+// https://www.w3.org/TR/css-text-3/#white-space-phase-1
+fn collapse_white_space(text: &str, white_space: WhiteSpace, mut after_space: bool) -> String {
+    if !white_space.collapses_spaces() {
+        return text.to_string();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_white_space(c) {
+            result.push(c);
+            after_space = false;
+            continue;
+        }
+        let mut run = c.to_string();
+        while let Some(&c) = chars.peek().filter(|c| is_white_space(**c)) {
+            run.push(c);
+            chars.next();
+        }
+        let newlines = run.replace("\r\n", "\n").chars().filter(|c| *c == '\n' || *c == '\r').count();
+        if white_space.preserves_newlines() && newlines > 0 {
+            // the spaces and tabs around a newline go with it
+            result.extend(std::iter::repeat_n('\n', newlines));
+            after_space = true;
+        } else if !after_space {
+            result.push(' ');
+            after_space = true;
+        }
+    }
+    result
+}
+
+// drop the collapsible spaces that would end a line, at the end of a block
+// and before a block
+fn trim_trailing_space(boxes: &mut Vec<LayoutBox>) {
+    while let Some(last) = boxes.last_mut() {
+        let collapses = match last.box_type {
+            BoxType::InlineNode(node) => node.white_space().collapses_spaces(),
+            _ => false,
+        };
+        match &mut last.text {
+            Some(text) if collapses => {
+                let trimmed_len = text.trim_end_matches(' ').len();
+                text.truncate(trimmed_len);
+                if !text.is_empty() {
+                    return;
+                }
+                boxes.pop();
+            }
+            Some(_) => return,
+            None if matches!(last.box_type, BoxType::InlineNode(_)) => {
+                trim_trailing_space(&mut last.children);
+                return;
+            }
+            None => return,
+        }
+    }
+}
+
 impl<'a> LayoutBox<'a> {
     fn layout(&mut self, containing_block: Dimensions) {
         match self.box_type {
@@ -200,11 +377,7 @@ impl<'a> LayoutBox<'a> {
                 if margin_left == auto {margin_left = Value::Length(0.0, Unit::Px);}
                 if margin_right == auto {margin_right = Value::Length(0.0, Unit::Px);}
 
-                if underflow > 0.000001 {
-                    width = Value::Length(underflow, Unit::Px);
-                } else {
-                    width = Value::Length(underflow, Unit::Px);
-                }
+                width = Value::Length(underflow, Unit::Px);
             }
             (false, true, true) => {
                 margin_left = Value::Length(underflow/2.0, Unit::Px);
@@ -246,7 +419,80 @@ impl<'a> LayoutBox<'a> {
         for child in &mut self.children {
             child.layout(*d);
             // Track the height so each child is laid out below the previous content.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+            d.content.height += child.dimensions.margin_box().height;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+    use crate::style;
+
+    // the text of every text box, in order
+    fn texts(layout_box: &LayoutBox, result: &mut Vec<String>) {
+        result.extend(layout_box.text.clone());
+        for child in &layout_box.children {
+            texts(child, result);
+        }
+    }
+
+    #[test]
+    fn test_white_space_processing() {
+        let source = "<div>\n  <p>\n    Hello   <b>big</b>\n    world\n  </p>\n  <pre>  a\n    b</pre>\n  <p class=lines>one  \n   two</p>\n</div>";
+        let root = html::Parser::new(source).parse().unwrap();
+        let style_sheet = css::parse("div { display: block; } p { display: block; } pre { display: block; } .lines { white-space: pre-line; }");
+        let styled = style::style_tree(&root, &style_sheet);
+        let layout = build_layout_tree(&styled);
+
+        let mut result = Vec::new();
+        texts(&layout, &mut result);
+        assert_eq!(result, vec!["Hello ", "big", " world", "  a\n    b", "one\ntwo"]);
+
+        // styling the arena tree gives the same boxes
+        let document = dom::Document::from_node(root);
+        let styled = style::style_document(&document, &style_sheet);
+        let mut arena_result = Vec::new();
+        texts(&build_layout_tree(&styled), &mut arena_result);
+        assert_eq!(arena_result, result);
+    }
+
+    #[test]
+    fn test_display() {
+        let source = "<div><p>one <b>two</b></p><p hidden>gone</p><span class=none>none</span><title>t</title>a <br> b<svg><p>x</p></svg></div>";
+        let root = html::Parser::new(source).parse().unwrap();
+        let style_sheet = css::parse(".none { display: none; }");
+        let styled = style::style_tree(&root, &style_sheet);
+        let layout = build_layout_tree(&styled);
+
+        // what isn't shown makes no box, and a line break ends the space before it
+        let mut result = Vec::new();
+        texts(&layout, &mut result);
+        assert_eq!(result, vec!["one ", "two", "a", "b", "x"]);
+        // blocks by the user agent styles, which leave foreign elements inline
+        let kinds: Vec<bool> = layout.children.iter()
+            .map(|child| matches!(child.box_type, BoxType::BlockNode(_)))
+            .collect();
+        assert!(matches!(layout.box_type, BoxType::BlockNode(_)));
+        assert_eq!(kinds, vec![true, false, false, false, false]);
+    }
+
+    #[test]
+    fn test_collapse_white_space() {
+        assert_eq!(collapse_white_space("  a \t b\n\n c  ", WhiteSpace::Normal, true), "a b c ");
+        assert_eq!(collapse_white_space("  a", WhiteSpace::Nowrap, false), " a");
+        assert_eq!(collapse_white_space(" a  \n  b \r\n\r\nc", WhiteSpace::PreLine, false), " a\nb\n\nc");
+        assert_eq!(collapse_white_space("  a \n\t b", WhiteSpace::Pre, true), "  a \n\t b");
+        assert_eq!(collapse_white_space("a  b", WhiteSpace::PreWrap, false), "a  b");
+    }
+
+    #[test]
+    fn test_inner_text() {
+        let source = "<div>\n  <h1>Title</h1>\n  <p>one   <b>two</b>\n three</p><p hidden>gone</p><p>a <br> b</p>\n  <ul><li>x</li><li>y <span style>z</span></li></ul>\n  <script>var a;</script><pre> k\n  l</pre>end\n</div>";
+        let root = html::Parser::new(source).parse().unwrap();
+        let style_sheet = css::parse("span { display: none; }");
+        let styled = style::style_tree(&root, &style_sheet);
+        assert_eq!(styled.inner_text(), "Title\n\none two three\n\na\nb\n\nx\ny\n k\n  l\nend");
+    }
+}
@@ -1,13 +1,12 @@
 use browser::html;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
 
 fn main() {
-    let mut file = File::open("files/test.htm").unwrap();
-    let mut contents = String::new();
-    let a = &mut contents;
-    file.read_to_string(a).expect("Error while reading");
+    let bytes = fs::read("files/test.htm").expect("Error while reading");
+    let input = html::decode(&bytes);
 
-    let html = html::Parser::new(contents).parse();
-    println!("{}", html);
+    match html::Parser::new(input.text).parse() {
+        Ok(html) => println!("{:#}", html),
+        Err(err) => eprintln!("files/test.htm:{}", err),
+    }
 }
@@ -5,45 +5,33 @@ use crate::layout::Display;
 
 type PropertyMap = std::collections::HashMap<String, css::Value>;
 
+// properties an element takes from its parent unless a rule sets them
+const INHERITED_PROPERTIES: &[&str] = &["white-space"];
+// html elements browsers lay out as blocks
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "center", "dd", "details", "dialog",
+    "dir", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "header", "hgroup", "hr", "html", "legend", "li", "listing", "main",
+    "menu", "nav", "ol", "p", "plaintext", "pre", "section", "summary", "ul", "xmp",
+];
+// html elements browsers don't show
+const HIDDEN_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "datalist", "head", "link", "meta", "noembed", "noframes",
+    "param", "rp", "script", "style", "template", "title",
+];
+
 pub struct StyledNode<'a> {
-    pub node: &'a dom::Node,
+    pub node_type: &'a dom::NodeType,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
 }
 
-fn matches(elem: &dom::ElementData, selector: &css::Selector) -> bool {
-    match selector {
-        css::Selector::Simple(simple_selector) => matches_simple_selector(elem, simple_selector),
-        _ => false
-    }
-}
-
-fn matches_simple_selector(elem: &dom::ElementData, selector: &css::SimpleSelector) -> bool {
-    // if selector.tag_name not equal to elem.tag_name; it means dismatching
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
-        return false;
-    }
-
-    // if selector.id not equal to elem's id attr; it means dismatching
-    if selector.id.iter().any(|id| elem.id() != Some(id)) {
-        return false;
-    }
-
-    // if any class in selector isn't contained by elem's classes; it means dismatching
-    let elem_classes = elem.classes();
-    if selector.class.iter().any(|_class| !elem_classes.contains(&**_class)) {
-        return false;
-    }
-    
-    return true;
-}
-
 type MatchedRule<'a> = (css::Specificity, &'a css::Rule);
 
 fn match_rule<'a>(elem: &dom::ElementData, rule: &'a css::Rule) -> Option<MatchedRule<'a>> {
     // this means if we can find a selector that matches with elem from this rule's selectors
     rule.selectors.iter()
-        .find(|selector| matches(elem, selector))
+        .find(|selector| selector.matches(elem))
         .map(|selector| (selector.specificity(), rule))
 }
 
@@ -52,21 +40,78 @@ fn matching_rules<'a>(elem: &dom::ElementData, style_sheet: &'a css::StyleSheet)
 }
 
 pub fn style_tree<'a>(root: &'a dom::Node, style_sheet: &'a css::StyleSheet) -> StyledNode<'a> {
+    styled_node(root, style_sheet, &PropertyMap::new())
+}
+
+/// Style the tree of a `dom::Document` from its root.
+pub fn style_document<'a>(document: &'a dom::Document, style_sheet: &'a css::StyleSheet) -> StyledNode<'a> {
+    styled_document_node(document, document.root(), style_sheet, &PropertyMap::new())
+}
+
+fn styled_node<'a>(node: &'a dom::Node, style_sheet: &'a css::StyleSheet, parent_values: &PropertyMap) -> StyledNode<'a> {
+    let values = node_values(&node.node_type, style_sheet, parent_values);
     StyledNode {
-        node: root,
-        specified_values: match &root.node_type {
-            dom::NodeType::Element(elem) => specified_values(elem, style_sheet),
-            _ => PropertyMap::new(),
-        },
-        children: root.children.iter().map(|child_node| style_tree(child_node, style_sheet)).collect(),
+        node_type: &node.node_type,
+        children: node.children.iter().map(|child_node| styled_node(child_node, style_sheet, &values)).collect(),
+        specified_values: values,
     }
 }
 
-fn specified_values(elem: &dom::ElementData, style_sheet: &css::StyleSheet) -> PropertyMap {
+fn styled_document_node<'a>(document: &'a dom::Document, id: dom::NodeId, style_sheet: &'a css::StyleSheet, parent_values: &PropertyMap) -> StyledNode<'a> {
+    let values = node_values(document.node_type(id), style_sheet, parent_values);
+    StyledNode {
+        node_type: document.node_type(id),
+        children: document.children(id).map(|child| styled_document_node(document, child, style_sheet, &values)).collect(),
+        specified_values: values,
+    }
+}
+
+fn node_values(node_type: &dom::NodeType, style_sheet: &css::StyleSheet, parent_values: &PropertyMap) -> PropertyMap {
+    // text gets the inherited values too, it is laid out by them
+    let mut values: PropertyMap = parent_values.iter()
+        .filter(|(name, _)| INHERITED_PROPERTIES.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    if let dom::NodeType::Element(elem) = node_type {
+        values.extend(specified_values(elem, style_sheet));
+    }
+    values
+}
+
+// the styles browsers give elements before any stylesheet
+fn user_agent_values(elem: &dom::ElementData) -> PropertyMap {
     let mut values = PropertyMap::new();
+    if elem.is_foreign() {
+        return values;
+    }
+    let tag_name = elem.tag_name.to_ascii_lowercase();
+    let display = if HIDDEN_ELEMENTS.contains(&&tag_name[..]) || elem.attributes.contains_key("hidden") {
+        Some("none")
+    } else if BLOCK_ELEMENTS.contains(&&tag_name[..]) {
+        Some("block")
+    } else {
+        None
+    };
+    if let Some(display) = display {
+        values.insert("display".to_string(), css::Value::Keyword(display.to_string()));
+    }
+    let white_space = match &tag_name[..] {
+        "pre" | "listing" | "xmp" | "plaintext" => Some("pre"),
+        "textarea" => Some("pre-wrap"),
+        "nobr" => Some("nowrap"),
+        _ => None,
+    };
+    if let Some(white_space) = white_space {
+        values.insert("white-space".to_string(), css::Value::Keyword(white_space.to_string()));
+    }
+    values
+}
+
+fn specified_values(elem: &dom::ElementData, style_sheet: &css::StyleSheet) -> PropertyMap {
+    let mut values = user_agent_values(elem);
     let mut rules = matching_rules(elem, style_sheet);
 
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    rules.sort_by_key(|&(specificity, _)| specificity);
     for (_, rule) in rules {
         for declaration in &rule.declarations {
             values.insert(declaration.name.clone(), declaration.value.clone());
@@ -75,3 +120,45 @@ fn specified_values(elem: &dom::ElementData, style_sheet: &css::StyleSheet) -> P
     
     values
 }
+
+/// How white space in text is laid out, from the `white-space` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+    BreakSpaces,
+}
+
+impl WhiteSpace {
+    /// Runs of spaces and tabs are shown as one space.
+    pub fn collapses_spaces(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine)
+    }
+    /// Newlines in the text break the line.
+    pub fn preserves_newlines(self) -> bool {
+        !matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap)
+    }
+    /// Lines may break to fit the width of their box.
+    pub fn wraps(self) -> bool {
+        !matches!(self, WhiteSpace::Pre | WhiteSpace::Nowrap)
+    }
+}
+
+impl StyledNode<'_> {
+    pub fn white_space(&self) -> WhiteSpace {
+        match self.specified_values.get("white-space") {
+            Some(css::Value::Keyword(keyword)) => match &keyword[..] {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                "break-spaces" => WhiteSpace::BreakSpaces,
+                _ => WhiteSpace::Normal,
+            },
+            _ => WhiteSpace::Normal,
+        }
+    }
+}